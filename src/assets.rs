@@ -0,0 +1,546 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::{Component, Path, PathBuf},
+};
+
+use xml::{attribute::OwnedAttribute, reader::XmlEvent};
+
+use crate::minify::{minify_css, minify_js};
+
+pub const MANIFEST_FILE: &str = "asset-manifest.json";
+
+/// Fingerprinted names of static files, like `/css/main.3f9a1c2b.css` for `/css/main.css`.
+/// References to them in `src` and `href` attributes are rewritten when rendering. Relative
+/// references are resolved against the page's directory, and stay relative.
+#[derive(Default, Hash)]
+pub struct Fingerprints {
+    urls: BTreeMap<String, String>,
+}
+
+impl Fingerprints {
+    /// Records a fingerprint for `path`, relative to the output directory, and returns the
+    /// fingerprinted path.
+    pub fn insert(&mut self, path: &Path, hash: u64) -> PathBuf {
+        let hash = &format!("{hash:016x}")[..8];
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let name = match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => format!("{stem}.{hash}.{ext}"),
+            _ => format!("{name}.{hash}"),
+        };
+        let hashed = path.with_file_name(name);
+
+        let url = |path: &Path| {
+            let path = path.to_string_lossy().replace('\\', "/");
+            String::from("/") + path.trim_start_matches("./")
+        };
+        self.urls.insert(url(path), url(&hashed));
+        hashed
+    }
+
+    /// The fingerprinted version of `url`, keeping its query and fragment. A relative `url` is
+    /// resolved against `dir`, the directory of the page it's in (like `/blog/`), and is only
+    /// rewritten if that's known.
+    pub fn get(&self, url: &str, dir: Option<&str>) -> Option<String> {
+        let end = url.find(['?', '#']).unwrap_or(url.len());
+        let (path, rest) = url.split_at(end);
+        if path.starts_with('/') {
+            return self.urls.get(path).map(|hashed| hashed.clone() + rest);
+        }
+
+        let dir = dir.filter(|_| is_relative(path))?;
+        let hashed = self.urls.get(&join_url(dir, path))?;
+        // The fingerprinted file is next to the original, so only the file name changes.
+        let name = &hashed[hashed.rfind('/').map_or(0, |i| i + 1)..];
+        let parent = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+        Some(format!("{parent}{name}{rest}"))
+    }
+
+    /// Rewrites `src` and `href` attributes that refer to a fingerprinted file.
+    pub fn rewrite_attributes(&self, attributes: &mut [OwnedAttribute], dir: Option<&str>) {
+        for attribute in attributes {
+            if matches!(attribute.name.local_name.as_str(), "src" | "href") {
+                if let Some(hashed) = self.get(&attribute.value, dir) {
+                    attribute.value = hashed;
+                }
+            }
+        }
+    }
+
+    /// Rewrites `src` and `href` attributes in `events` that refer to a fingerprinted file.
+    pub fn rewrite(&self, events: Vec<XmlEvent>, dir: Option<&str>) -> Vec<XmlEvent> {
+        if self.urls.is_empty() {
+            return events;
+        }
+
+        events
+            .into_iter()
+            .map(|event| match event {
+                XmlEvent::StartElement {
+                    name,
+                    mut attributes,
+                    namespace,
+                } => {
+                    self.rewrite_attributes(&mut attributes, dir);
+                    XmlEvent::StartElement {
+                        name,
+                        attributes,
+                        namespace,
+                    }
+                }
+                event => event,
+            })
+            .collect()
+    }
+
+    /// Writes every fingerprinted URL to `asset-manifest.json` in `out_path`.
+    pub fn write_manifest(&self, out_path: &Path) -> io::Result<()> {
+        let file = BufWriter::new(File::create(out_path.join(MANIFEST_FILE))?);
+        serde_json::to_writer_pretty(file, &self.urls).map_err(io::Error::other)
+    }
+}
+
+/// Bundles a CSS or JavaScript entry point with the files it imports, and minifies it if
+/// `minify` is set. `root` is the static directory, which imports starting with `/` are relative
+/// to.
+pub fn bundle(entry: &Path, root: &Path, minify: bool) -> Result<String, String> {
+    match entry.extension().and_then(|ext| ext.to_str()) {
+        Some("css") => {
+            let mut seen = Vec::new();
+            let (remote, body) = bundle_css(entry, root, entry.parent(), &mut seen)?;
+            let bundled = remote.join("\n") + "\n" + &body;
+            Ok(if minify {
+                minify_css(&bundled)
+            } else {
+                bundled
+            })
+        }
+        Some("js" | "mjs") => {
+            let mut bundler = JsBundler {
+                root,
+                modules: Vec::new(),
+                ids: HashMap::new(),
+                stack: Vec::new(),
+            };
+            bundler.add(entry)?;
+            let bundled = format!("(() => {{\n{}}})();\n", bundler.modules.join(""));
+            Ok(if minify { minify_js(&bundled) } else { bundled })
+        }
+        _ => Err(String::from("Unknown kind of asset (expected .css or .js)")),
+    }
+}
+
+fn read(path: &Path) -> Result<(PathBuf, String), String> {
+    let canonical = fs::canonicalize(path)
+        .map_err(|err| format!("Failed to read {}: {err}", path.to_string_lossy()))?;
+    let source = fs::read_to_string(&canonical)
+        .map_err(|err| format!("Failed to read {}: {err}", path.to_string_lossy()))?;
+    Ok((canonical, source))
+}
+
+/// Where an import refers to, relative to the importing file in `dir`, or to `root` if it starts
+/// with `/`. Returns `None` for URLs.
+fn resolve(specifier: &str, dir: &Path, root: &Path) -> Option<PathBuf> {
+    if specifier.contains("://") || specifier.starts_with("//") || specifier.starts_with("data:") {
+        None
+    } else if let Some(specifier) = specifier.strip_prefix('/') {
+        Some(root.join(specifier))
+    } else {
+        Some(dir.join(specifier))
+    }
+}
+
+/// Whether `url` is relative to the page it's in, rather than to the site root or another site.
+fn is_relative(url: &str) -> bool {
+    !url.is_empty()
+        && !url.starts_with(['/', '#', '?'])
+        && url
+            .find(':')
+            .is_none_or(|i| url[..i].contains(['/', '?', '#']))
+}
+
+/// The root-relative URL of `url`, relative to the directory `dir`.
+fn join_url(dir: &str, url: &str) -> String {
+    let path = Path::new(dir).join(url);
+    let parts: Vec<_> = normalize(&path)
+        .into_iter()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect();
+    String::from("/") + &parts.join("/")
+}
+
+/// Removes `.` and (where possible) `..` components from `path`, without touching the file
+/// system.
+fn normalize(path: &Path) -> Vec<Component<'_>> {
+    let mut components = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(components.last(), Some(Component::Normal(_))) => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    components
+}
+
+/// The path to `to` from the directory `from`.
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from = normalize(from);
+    let to = normalize(to);
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut path = PathBuf::new();
+    for _ in common..from.len() {
+        path.push(Component::ParentDir);
+    }
+    for component in &to[common..] {
+        path.push(component);
+    }
+    path
+}
+
+/// Reads a quoted string at the start of `text`, returning it and the rest of `text`.
+fn quoted(text: &str) -> Option<(&str, &str)> {
+    let quote = text.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let end = text[1..].find(quote)? + 1;
+    Some((&text[1..end], &text[end + 1..]))
+}
+
+/// Bundles a stylesheet, replacing its `@import`s with the imported files. Imports of URLs are
+/// returned separately, since they have to come first. Files that were already imported (listed
+/// in `seen`) are skipped. `url()`s in imported files are rewritten to be relative to `entry_dir`.
+fn bundle_css(
+    path: &Path,
+    root: &Path,
+    entry_dir: Option<&Path>,
+    seen: &mut Vec<PathBuf>,
+) -> Result<(Vec<String>, String), String> {
+    let (canonical, source) = read(path)?;
+    if seen.contains(&canonical) {
+        return Ok((Vec::new(), String::new()));
+    }
+    seen.push(canonical);
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    let mut remote = Vec::new();
+    let mut bundled = String::new();
+    let mut rest = source.as_str();
+    loop {
+        rest = rest.trim_start();
+        if let Some(comment) = rest.strip_prefix("/*") {
+            rest = comment.find("*/").map_or("", |end| &comment[end + 2..]);
+            continue;
+        }
+        if rest.starts_with("@charset") {
+            rest = rest.find(';').map_or("", |end| &rest[end + 1..]);
+            continue;
+        }
+        let Some(import) = rest.strip_prefix("@import") else {
+            break;
+        };
+
+        let end = import.find(';').unwrap_or(import.len());
+        let statement = import[..end].trim();
+        rest = import.get(end + 1..).unwrap_or_default();
+
+        let (url, media) = match statement.strip_prefix("url(") {
+            Some(url) => {
+                let close = url.find(')').unwrap_or(url.len());
+                let (inner, media) = (&url[..close], url.get(close + 1..).unwrap_or_default());
+                let inner = inner.trim();
+                (quoted(inner).map_or(inner, |(url, _)| url), media)
+            }
+            None => quoted(statement)
+                .ok_or_else(|| format!("Invalid @import in {}", path.to_string_lossy()))?,
+        };
+        let media = media.trim();
+
+        match resolve(url, dir, root) {
+            Some(imported) => {
+                let (mut imported_remote, css) = bundle_css(&imported, root, entry_dir, seen)?;
+                remote.append(&mut imported_remote);
+                if media.is_empty() || css.is_empty() {
+                    bundled.push_str(&css);
+                } else {
+                    bundled.push_str(&format!("@media {media} {{\n{css}}}\n"));
+                }
+            }
+            None => remote.push(format!("@import {statement};")),
+        }
+    }
+
+    bundled.push_str(&match entry_dir {
+        Some(entry_dir) if entry_dir != dir => rebase_urls(rest, dir, entry_dir),
+        _ => rest.to_owned(),
+    });
+    bundled.push('\n');
+    Ok((remote, bundled))
+}
+
+/// Rewrites relative `url()`s in `css`, a file in `dir`, to be relative to `entry_dir`.
+fn rebase_urls(css: &str, dir: &Path, entry_dir: &Path) -> String {
+    let mut rebased = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("url(") {
+        rebased.push_str(&rest[..start + 4]);
+        rest = &rest[start + 4..];
+        let close = rest.find(')').unwrap_or(rest.len());
+        let inner = rest[..close].trim();
+        let (url, quote) = match quoted(inner) {
+            Some((url, _)) => (url, &inner[..1]),
+            None => (inner, ""),
+        };
+
+        match resolve(url, dir, Path::new("/")) {
+            Some(_) if !url.starts_with('/') && !url.starts_with('#') => {
+                let url = relative_path(entry_dir, &dir.join(url));
+                rebased.push_str(&format!(
+                    "{quote}{}{quote}",
+                    url.to_string_lossy().replace('\\', "/")
+                ));
+            }
+            _ => rebased.push_str(&rest[..close]),
+        }
+        rest = &rest[close..];
+    }
+    rebased.push_str(rest);
+    rebased
+}
+
+/// Bundles ES modules into a single script. Each module is wrapped in a function, which returns
+/// its exports, and `import` and `export` statements (which have to start a line) are rewritten to
+/// use them.
+struct JsBundler<'a> {
+    root: &'a Path,
+    modules: Vec<String>,
+    ids: HashMap<PathBuf, usize>,
+    /// The modules currently being added, to detect cycles.
+    stack: Vec<PathBuf>,
+}
+
+impl JsBundler<'_> {
+    fn add(&mut self, path: &Path) -> Result<usize, String> {
+        let (canonical, source) = read(path)?;
+        if let Some(id) = self.ids.get(&canonical) {
+            return Ok(*id);
+        }
+        if self.stack.contains(&canonical) {
+            return Err(format!(
+                "Modules import each other: {} -> {}",
+                self.stack
+                    .iter()
+                    .map(|path| path.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(" -> "),
+                canonical.to_string_lossy()
+            ));
+        }
+
+        self.stack.push(canonical.clone());
+        let body = self.transform(&source, path)?;
+        self.stack.pop();
+
+        let id = self.modules.len();
+        self.modules.push(format!(
+            "const __sr_{id} = (() => {{\nconst __exports = {{}};\n{body}\nreturn __exports;\n}})();\n"
+        ));
+        self.ids.insert(canonical, id);
+        Ok(id)
+    }
+
+    fn import(&mut self, specifier: &str, path: &Path) -> Result<usize, String> {
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let mut imported = resolve(specifier, dir, self.root)
+            .filter(|_| specifier.starts_with('.') || specifier.starts_with('/'))
+            .ok_or_else(|| {
+                format!(
+                    "Only relative imports can be bundled, not '{specifier}' (imported by {})",
+                    path.to_string_lossy()
+                )
+            })?;
+        if imported.extension().is_none() && !imported.exists() {
+            imported.set_extension("js");
+        }
+        self.add(&imported)
+    }
+
+    fn transform(&mut self, source: &str, path: &Path) -> Result<String, String> {
+        let unsupported = |statement: &str| {
+            format!(
+                "Can't bundle '{}' (in {})",
+                statement.lines().next().unwrap_or_default(),
+                path.to_string_lossy()
+            )
+        };
+
+        // Exports are defined as getters up front, so they see later assignments.
+        let mut exports = Vec::new();
+        let mut body = String::new();
+        let mut rest = source;
+
+        while !rest.is_empty() {
+            let line_end = rest.find('\n').map_or(rest.len(), |end| end + 1);
+            let trimmed = rest.trim_start_matches([' ', '\t']);
+            let indent = &rest[..rest.len() - trimmed.len()];
+
+            let is_import = trimmed.starts_with("import")
+                && trimmed[6..].starts_with([' ', '{', '*', '"', '\'']);
+            let is_export =
+                trimmed.starts_with("export") && trimmed[6..].starts_with([' ', '{', '*']);
+            if !is_import && !is_export {
+                body.push_str(&rest[..line_end]);
+                rest = &rest[line_end..];
+                continue;
+            }
+            body.push_str(indent);
+
+            if is_import {
+                // The module specifier is the first string in the statement.
+                let start = trimmed
+                    .find(['"', '\''])
+                    .ok_or_else(|| unsupported(trimmed))?;
+                let (specifier, after) =
+                    quoted(&trimmed[start..]).ok_or_else(|| unsupported(trimmed))?;
+                let clause = trimmed[6..start].trim();
+                let clause = clause.strip_suffix("from").unwrap_or(clause).trim();
+                let id = self.import(specifier, path)?;
+                body.push_str(&import_bindings(clause, id).ok_or_else(|| unsupported(trimmed))?);
+                rest = after.trim_start_matches([' ', '\t']);
+                rest = rest.strip_prefix(';').unwrap_or(rest);
+                continue;
+            }
+
+            let statement = trimmed[6..].trim_start();
+            if let Some(default) = statement.strip_prefix("default") {
+                let default = default.trim_start();
+                let declared = ["function", "async function", "class"]
+                    .iter()
+                    .find_map(|keyword| default.strip_prefix(keyword))
+                    .map(|declaration| identifier(declaration.trim_start_matches(['*', ' '])))
+                    .filter(|name| !name.is_empty());
+                match declared {
+                    Some(name) => exports.push((String::from("default"), name.to_owned())),
+                    None => {
+                        exports.push((String::from("default"), String::from("__default")));
+                        body.push_str("const __default = ");
+                    }
+                }
+                rest = default;
+            } else if statement.starts_with('{') || statement.starts_with('*') {
+                let (names, after) = match statement.strip_prefix('{') {
+                    Some(names) => {
+                        let close = names.find('}').ok_or_else(|| unsupported(trimmed))?;
+                        (Some(&names[..close]), &names[close + 1..])
+                    }
+                    None => (None, &statement[1..]),
+                };
+                let after = after.trim_start();
+                let from = match after.strip_prefix("from") {
+                    Some(from) => {
+                        let (specifier, after) =
+                            quoted(from.trim_start()).ok_or_else(|| unsupported(trimmed))?;
+                        rest = after;
+                        Some(self.import(specifier, path)?)
+                    }
+                    None => {
+                        rest = after;
+                        None
+                    }
+                };
+                rest = rest.trim_start_matches([' ', '\t']);
+                rest = rest.strip_prefix(';').unwrap_or(rest);
+
+                match (names, from) {
+                    (Some(names), from) => {
+                        for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                            let (local, exported) = name
+                                .split_once(" as ")
+                                .map_or((name, name), |(l, e)| (l.trim(), e.trim()));
+                            let local = match from {
+                                Some(id) => format!("__sr_{id}.{local}"),
+                                None => local.to_owned(),
+                            };
+                            exports.push((exported.to_owned(), local));
+                        }
+                    }
+                    (None, Some(id)) => body.push_str(&format!(
+                        "for (const key of Object.keys(__sr_{id})) if (key !== \"default\") \
+                         Object.defineProperty(__exports, key, {{ get: () => __sr_{id}[key], enumerable: true }});"
+                    )),
+                    (None, None) => return Err(unsupported(trimmed)),
+                }
+            } else {
+                let declaration = ["const", "let", "var", "async function", "function", "class"]
+                    .iter()
+                    .find_map(|keyword| statement.strip_prefix(keyword))
+                    .ok_or_else(|| unsupported(trimmed))?;
+                let name = identifier(declaration.trim_start_matches(['*', ' ']));
+                if name.is_empty() {
+                    return Err(unsupported(trimmed));
+                }
+                exports.push((name.to_owned(), name.to_owned()));
+                rest = statement;
+            }
+        }
+
+        let getters: String = exports
+            .iter()
+            .map(|(exported, local)| {
+                format!(
+                    "Object.defineProperty(__exports, \"{exported}\", {{ get: () => {local}, enumerable: true }});\n"
+                )
+            })
+            .collect();
+        Ok(getters + &body)
+    }
+}
+
+/// The identifier at the start of `text`.
+fn identifier(text: &str) -> &str {
+    let end = text
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+        .unwrap_or(text.len());
+    &text[..end]
+}
+
+/// Declarations for the bindings of an import clause, like `a, { b as c }` or `* as ns`, from the
+/// module `id`.
+fn import_bindings(clause: &str, id: usize) -> Option<String> {
+    let module = format!("__sr_{id}");
+    let mut bindings = String::new();
+    let mut rest = clause;
+
+    while !rest.is_empty() {
+        if let Some(namespace) = rest.strip_prefix('*') {
+            let name = namespace.trim_start().strip_prefix("as")?.trim();
+            bindings.push_str(&format!("const {} = {module};", identifier(name)));
+            rest = &name[identifier(name).len()..];
+        } else if let Some(names) = rest.strip_prefix('{') {
+            let close = names.find('}')?;
+            let names: Vec<_> = names[..close]
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(|name| name.replacen(" as ", ": ", 1))
+                .collect();
+            bindings.push_str(&format!("const {{ {} }} = {module};", names.join(", ")));
+            rest = &rest[close + 2..];
+        } else {
+            let name = identifier(rest);
+            if name.is_empty() {
+                return None;
+            }
+            bindings.push_str(&format!("const {name} = {module}.default;"));
+            rest = &rest[name.len()..];
+        }
+        rest = rest.trim_start().trim_start_matches(',').trim_start();
+    }
+
+    Some(bindings)
+}
@@ -1,15 +1,41 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
     fmt::Display,
     fs::{self, File},
-    io::{self, BufReader, BufWriter, Write},
+    io::{self, BufReader, Write},
+    num::NonZero,
     path::{Path, PathBuf},
     process::Command,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
-use crate::xml::Template;
-use crate::{config::Config, xml::TemplateError};
-use xml::{
+use serde_json::{json, Value};
+
+use crate::xml::{self, Template, TemplateContext};
+use crate::{
+    assets::{self, Fingerprints},
+    collection::Collections,
+    component::Components,
+    config::Config,
+    data::Data,
+    date::Date,
+    feed,
+    highlight::Highlighter,
+    links::{self, BrokenLink},
+    manifest::{self, FileRecord, Manifest, PageRecord},
+    markdown,
+    plugin::{self, Hook, Plugin},
+    sitemap,
+    xml::TemplateError,
+};
+use ::xml::{
+    common::{Position, TextPosition},
     reader::{self, XmlEvent},
     writer,
 };
@@ -21,6 +47,83 @@ pub enum Verbosity {
     High,
 }
 
+/// Receives the progress of a build, or of the dev server, which logs from several threads.
+/// [`Verbosity`] logs it to stdout: everything at the `high` level, and only statuses, warnings
+/// and errors at the `low` level.
+pub trait Logger: Sync {
+    /// A step of the build started, like parsing the template. It ends with [`Logger::done`].
+    fn start(&self, step: &str);
+    /// The last step is done, with a note like the number of files that were up to date.
+    fn done(&self, note: Option<&str>);
+    /// Something the build did, like copying a file.
+    fn info(&self, message: &str);
+
+    /// Something to show even in a short log, like a finished build or a served request.
+    fn status(&self, message: &str) {
+        self.info(message);
+    }
+
+    /// A warning, like a collection without pages.
+    fn warn(&self, message: &str) {
+        self.info(message);
+    }
+
+    /// An error, like a failed build.
+    fn error(&self, message: &str) {
+        self.info(message);
+    }
+}
+
+/// Whether [`Verbosity`] printed a step without ending its line, which happens if it fails.
+static STEP_PENDING: AtomicBool = AtomicBool::new(false);
+
+impl Logger for Verbosity {
+    fn start(&self, step: &str) {
+        if *self == Self::High {
+            print!("{step} ");
+            STEP_PENDING.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn done(&self, note: Option<&str>) {
+        if *self == Self::High {
+            STEP_PENDING.store(false, Ordering::Relaxed);
+            match note {
+                Some(note) => println!("Done! ({note})"),
+                None => println!("Done!"),
+            }
+        }
+    }
+
+    fn info(&self, message: &str) {
+        self.print(Self::High, message);
+    }
+
+    fn status(&self, message: &str) {
+        self.print(Self::Low, message);
+    }
+
+    fn warn(&self, message: &str) {
+        self.print(Self::Low, message);
+    }
+
+    fn error(&self, message: &str) {
+        self.print(Self::Low, message);
+    }
+}
+
+impl Verbosity {
+    /// Prints `message` on its own line, if the verbosity is at least `level`.
+    fn print(self, level: Self, message: &str) {
+        if self >= level {
+            if STEP_PENDING.swap(false, Ordering::Relaxed) {
+                println!();
+            }
+            println!("{message}");
+        }
+    }
+}
+
 impl TryFrom<&str> for Verbosity {
     type Error = ();
 
@@ -35,7 +138,10 @@ impl TryFrom<&str> for Verbosity {
 }
 
 pub enum BuildError {
-    Io(io::Error),
+    Io {
+        err: io::Error,
+        source: Option<String>,
+    },
     Parse {
         err: reader::Error,
         source: Option<String>,
@@ -48,12 +154,16 @@ pub enum BuildError {
         msg: String,
         source: Option<String>,
     },
+    Link(BrokenLink),
 }
 
 impl BuildError {
-    fn with_source(self, source: String) -> Self {
+    pub fn with_source(self, source: String) -> Self {
         match self {
-            Self::Io(_) => self,
+            Self::Io { err, .. } => Self::Io {
+                err,
+                source: Some(source),
+            },
             Self::Parse { err, .. } => Self::Parse {
                 err,
                 source: Some(source),
@@ -66,23 +176,92 @@ impl BuildError {
                 msg,
                 source: Some(source),
             },
+            Self::Link(link) => Self::Link(link),
+        }
+    }
+
+    /// The error, without where it happened.
+    pub fn message(&self) -> String {
+        match self {
+            Self::Io { err, .. } => err.to_string(),
+            Self::Parse { err, .. } => err.msg().to_owned(),
+            Self::Write { err, .. } => err.to_string(),
+            Self::Other { msg, .. } => msg.trim_end_matches('.').to_owned(),
+            Self::Link(link) => format!("Broken link to '{}' in <{}>", link.url, link.element),
+        }
+    }
+
+    /// The file the error happened in.
+    pub fn source(&self) -> Option<&str> {
+        match self {
+            Self::Io { source, .. }
+            | Self::Parse { source, .. }
+            | Self::Write { source, .. }
+            | Self::Other { source, .. } => source.as_deref(),
+            Self::Link(link) => link.file.to_str(),
         }
     }
+
+    /// Where in its source file a parse error happened. Markdown is parsed after being rendered
+    /// to HTML, so positions in Markdown pages aren't known.
+    pub fn position(&self) -> Option<TextPosition> {
+        match self {
+            Self::Parse { err, source } if source.as_ref().is_none_or(|s| !s.ends_with(".md")) => {
+                Some(err.position())
+            }
+            _ => None,
+        }
+    }
+
+    /// The line of the source file where the error happened, starting at 1.
+    pub fn line(&self) -> Option<u64> {
+        match self {
+            Self::Link(link) => link.line.map(|line| line as u64),
+            _ => self.position().map(|position| position.row + 1),
+        }
+    }
+
+    /// The line of the source file where the error happened, with a marker under the column.
+    pub fn code_frame(&self) -> Option<String> {
+        let position = self.position()?;
+        let source = fs::read_to_string(self.source()?).ok()?;
+        let line = source.lines().nth(position.row as usize)?;
+        let number = (position.row + 1).to_string();
+        let marker: String = line
+            .chars()
+            .take(position.column as usize)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let gutter = " ".repeat(number.len());
+        Some(format!(
+            "{gutter} |\n{number} | {line}\n{gutter} | {marker}^"
+        ))
+    }
 }
 
 impl From<io::Error> for BuildError {
     fn from(value: io::Error) -> Self {
-        Self::Io(value)
+        Self::Io {
+            err: value,
+            source: None,
+        }
     }
 }
 
 impl From<TemplateError> for BuildError {
     fn from(value: TemplateError) -> Self {
         match value {
-            TemplateError::Io(v) => Self::Io(v),
+            TemplateError::Io(v) => Self::from(v),
             TemplateError::Parse(err) => Self::Parse { err, source: None },
             TemplateError::Write(err) => Self::Write { err, source: None },
             TemplateError::MissingProp(name) => Self::Other { msg: format!("Missing property {name}."), source: None },
+            TemplateError::MissingComponent(name) => Self::Other { msg: format!("Unknown component '{name}'."), source: None },
+            TemplateError::ComponentCycle(stack) => Self::Other { msg: format!("Components include each other: {}.", stack.join(" -> ")), source: None },
+            TemplateError::FrontMatter(msg) => Self::Other { msg: format!("Invalid front matter: {msg}"), source: None },
+            TemplateError::MissingCollection(name) => Self::Other { msg: format!("Unknown collection '{name}'."), source: None },
+            TemplateError::MalformedAttribute(name) => Self::Other { msg: format!("Invalid value for attribute '{name}'."), source: None },
+            TemplateError::MalformedData(msg) => Self::Other { msg: format!("Invalid data file: {msg}"), source: None },
+            TemplateError::MissingData(path) => Self::Other { msg: format!("Missing or invalid value '{path}' in data."), source: None },
             TemplateError::MalformedProp(name) => Self::Other { msg: format!("Property '{name}' is non-alphanumeric or reserved.\n  (accepted: A-z 0-9 _; must not start with __)."), source: None },
         }
     }
@@ -90,49 +269,242 @@ impl From<TemplateError> for BuildError {
 
 impl Display for BuildError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message())?;
+        match (self.source(), self.position(), self.line()) {
+            (Some(source), Some(position), _) => write!(f, " in {source}:{position}"),
+            (Some(source), None, Some(line)) => write!(f, " in {source}:{line}"),
+            (Some(source), None, None) => write!(f, " in {source}"),
+            (None, _, _) => Ok(()),
+        }
+    }
+}
+
+/// What a build changed in the output directory.
+#[derive(Default)]
+pub struct BuildSummary {
+    pub copied: usize,
+    /// Static files and pages that were already up to date.
+    pub unchanged: usize,
+    pub removed: usize,
+    pub template_changed: bool,
+    /// URLs of the `.page.json` files that were rendered, e.g. `/cat.page.json`.
+    pub pages: Vec<String>,
+}
+
+/// The parts of a build. The one that failed decides the exit code.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Config,
+    Script,
+    Static,
+    Template,
+    Page,
+    Output,
+    Links,
+}
+
+impl Stage {
+    pub fn name(self) -> &'static str {
         match self {
-            Self::Io(v) => v.fmt(f),
-            Self::Parse { err, source } => write!(
-                f,
-                "{err} in {}",
-                if let Some(source) = source {
-                    source
-                } else {
-                    ""
-                }
-            ),
-            Self::Write { err, source } => write!(
-                f,
-                "{err} in {}",
-                if let Some(source) = source {
-                    source
-                } else {
-                    ""
-                }
-            ),
-            Self::Other { msg, source } => write!(
-                f,
-                "{msg} in {}",
-                if let Some(source) = source {
-                    source
-                } else {
-                    ""
-                }
-            ),
+            Self::Config => "config",
+            Self::Script => "script",
+            Self::Static => "static",
+            Self::Template => "template",
+            Self::Page => "page",
+            Self::Output => "output",
+            Self::Links => "links",
+        }
+    }
+
+    /// 2 for the config, 3 for the template, layouts, components and data, 4 for pages and their
+    /// links, 5 for scripts, and 1 for anything else, like failing to write the output.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Config => 2,
+            Self::Template => 3,
+            Self::Page | Self::Links => 4,
+            Self::Script => 5,
+            Self::Static | Self::Output => 1,
         }
     }
 }
 
-pub fn build(verbosity: Verbosity, config: Config) -> Result<(), BuildError> {
-    if let Some(cmd) = config.scripts.prebuild {
-        if verbosity == Verbosity::High {
-            println!("Running pre-build script... ");
+/// Everything a build or check reports, whether or not it succeeded.
+#[derive(Default)]
+pub struct BuildReport {
+    pub summary: BuildSummary,
+    pub warnings: Vec<String>,
+    pub errors: Vec<BuildError>,
+    /// How long each stage took, in order.
+    pub durations: Vec<(Stage, Duration)>,
+    /// The stage that stopped the build, or [`Stage::Page`] if only pages failed.
+    pub failed: Option<Stage>,
+    current: Option<(Stage, Instant)>,
+}
+
+impl BuildReport {
+    /// A report for a config that couldn't be read, before anything was built.
+    pub fn config_error(msg: String) -> Self {
+        Self {
+            errors: vec![BuildError::Other {
+                msg,
+                source: Some(String::from("simple-router.toml")),
+            }],
+            failed: Some(Stage::Config),
+            ..Default::default()
         }
-        let status = Command::new("sh").args(["-c", &cmd]).status()?;
-        if status.success() {
-            if verbosity == Verbosity::High {
-                println!("Done!");
+    }
+
+    fn enter(&mut self, stage: Stage) {
+        self.end_stage();
+        self.current = Some((stage, Instant::now()));
+    }
+
+    fn end_stage(&mut self) {
+        if let Some((stage, start)) = self.current.take() {
+            // The template is hashed before static files are copied, and parsed after.
+            match self.durations.iter_mut().find(|(s, _)| *s == stage) {
+                Some((_, duration)) => *duration += start.elapsed(),
+                None => self.durations.push((stage, start.elapsed())),
             }
+        }
+    }
+
+    /// Ends the report. An error in `result` stopped the build in the current stage.
+    fn finish(mut self, result: Result<(), BuildError>) -> Self {
+        let stage = self.current.map_or(Stage::Output, |(stage, _)| stage);
+        self.end_stage();
+        if let Err(err) = result {
+            self.errors.push(err);
+            self.failed = Some(stage);
+        } else if !self.errors.is_empty() && self.failed.is_none() {
+            self.failed = Some(Stage::Page);
+        }
+        self
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_none()
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        self.failed.map_or(0, Stage::exit_code)
+    }
+
+    /// Prints the warnings, then every error with a code frame where the position is known,
+    /// followed by how many there were.
+    pub fn print(&self) {
+        self.log(&Verbosity::High);
+    }
+
+    /// Sends the warnings and errors to `log`, like [`BuildReport::print`].
+    pub fn log(&self, log: &dyn Logger) {
+        for warning in &self.warnings {
+            log.warn(&format!("\x1b[33mWarning\x1b[0m: {warning}."));
+        }
+        if self.errors.is_empty() {
+            return;
+        }
+
+        for err in &self.errors {
+            log.error(&format!("\x1b[31mError\x1b[0m: {err}."));
+            if let Some(frame) = err.code_frame() {
+                log.error(&frame);
+            }
+        }
+        log.error(&format!(
+            "\n\x1b[31m{} error{}.\x1b[0m",
+            self.errors.len(),
+            if self.errors.len() == 1 { "" } else { "s" }
+        ));
+    }
+
+    /// The report as JSON, for `build --format json`.
+    pub fn to_json(&self) -> Value {
+        let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        json!({
+            "success": self.is_ok(),
+            "exit_code": self.exit_code(),
+            "failed_stage": self.failed.map(Stage::name),
+            "pages": self.summary.pages,
+            "copied": self.summary.copied,
+            "unchanged": self.summary.unchanged,
+            "removed": self.summary.removed,
+            "durations": self
+                .durations
+                .iter()
+                .map(|(stage, duration)| json!({ "stage": stage.name(), "ms": millis(*duration) }))
+                .collect::<Vec<_>>(),
+            "total_ms": millis(self.durations.iter().map(|(_, duration)| *duration).sum()),
+            "warnings": self.warnings,
+            "errors": self
+                .errors
+                .iter()
+                .map(|err| json!({
+                    "message": err.message(),
+                    "file": err.source(),
+                    "line": err.line(),
+                    "column": err.position().map(|position| position.column + 1),
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// The number of threads to build with by default: one per CPU.
+pub fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(1, NonZero::get)
+}
+
+/// Builds the site, copying static files and rendering pages on `jobs` threads. Pages that fail
+/// are skipped, so that every error is reported. `plugins` are called after the ones in the
+/// config.
+pub fn build(
+    config: &Config,
+    jobs: usize,
+    log: &dyn Logger,
+    plugins: &[&dyn Plugin],
+) -> BuildReport {
+    let plugins: Vec<&dyn Plugin> = config
+        .plugins
+        .iter()
+        .map(|plugin| plugin as &dyn Plugin)
+        .chain(plugins.iter().copied())
+        .collect();
+    let mut report = BuildReport::default();
+    let result = build_site(config, jobs, log, &plugins, &mut report);
+    let mut report = report.finish(result);
+
+    for plugin in plugins
+        .iter()
+        .filter(|plugin| plugin.handles(Hook::BuildFinished))
+    {
+        if let Err(msg) = plugin.build_finished(&report) {
+            report.errors.push(plugin::plugin_error(*plugin, msg, None));
+            report.failed.get_or_insert(Stage::Script);
+        }
+    }
+    report
+}
+
+/// Builds the site, adding errors in pages to the report. Other errors stop the build.
+fn build_site(
+    config: &Config,
+    jobs: usize,
+    log: &dyn Logger,
+    plugins: &[&dyn Plugin],
+    report: &mut BuildReport,
+) -> Result<(), BuildError> {
+    // Plugins written in Rust aren't in the config, so their names are hashed with it.
+    let plugin_names: Vec<&str> = plugins.iter().map(|plugin| plugin.name()).collect();
+    let config_hash = manifest::hash_value(&(env!("CARGO_PKG_VERSION"), &config, &plugin_names));
+
+    if let Some(cmd) = &config.scripts.prebuild {
+        report.enter(Stage::Script);
+        log.info("Running pre-build script...");
+        let status = run_script(cmd)?;
+        if status.success() {
+            log.done(None);
         } else {
             return Err(BuildError::Other {
                 msg: format!("Pre build script failed with exit code {status}"),
@@ -141,13 +513,31 @@ pub fn build(verbosity: Verbosity, config: Config) -> Result<(), BuildError> {
         }
     }
 
-    if verbosity == Verbosity::High {
-        println!("Creating output directory at {}", config.out.path);
-    }
+    report.enter(Stage::Template);
+    let template_path = PathBuf::from(&config.source.template);
+    let layout_files = list_files(Path::new(&config.source.layouts_path))?;
+    let component_files = list_files(Path::new(&config.source.components_path))?;
+    let data_files = list_files(Path::new(&config.source.data_path))?;
+    let template_hash = manifest::hash_value(
+        &[&template_path]
+            .into_iter()
+            .chain(&layout_files)
+            .chain(&component_files)
+            .chain(&data_files)
+            .map(|path| {
+                manifest::hash_file(path).map_err(|err| {
+                    BuildError::from(err).with_source(path.to_string_lossy().to_string())
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+    );
+
+    report.enter(Stage::Static);
+    log.info(&format!("Creating output directory at {}", config.out.path));
+
+    let previous = Manifest::load(&config.out.path).filter(|m| m.config == config_hash);
     if let Ok(metadata) = fs::metadata(&config.out.path) {
-        if metadata.is_dir() {
-            fs::remove_dir_all(&config.out.path)?;
-        } else {
+        if !metadata.is_dir() {
             return Err(BuildError::Other {
                 msg: format!(
                     "File exists at {path}, blocking output directory.",
@@ -155,17 +545,35 @@ pub fn build(verbosity: Verbosity, config: Config) -> Result<(), BuildError> {
                 ),
                 source: None,
             });
+        } else if previous.is_none() {
+            fs::remove_dir_all(&config.out.path)?;
+        } else {
+            Manifest::invalidate(&config.out.path)?;
         }
     }
+    let previous = previous.unwrap_or_default();
+    let mut manifest = Manifest::new(config_hash, template_hash);
+    let summary = &mut report.summary;
 
-    if PathBuf::from(&config.source.static_path) == PathBuf::from(&config.source.pages_path) {
+    if Path::new(&config.source.static_path) == Path::new(&config.source.pages_path) {
         return Err(BuildError::Other {
             msg: String::from("static_path cannot be the same as pages_path."),
             source: None,
         });
     }
 
-    for (file, out) in scan_dir(
+    let static_path = Path::new(&config.source.static_path);
+    let out_path = Path::new(&config.out.path);
+    let relative = |out: &Path| out.strip_prefix(out_path).unwrap_or(out).to_path_buf();
+    let mut fingerprints = Fingerprints::default();
+    let asset_entries: Vec<_> = config
+        .assets
+        .entries
+        .iter()
+        .map(|entry| static_path.join(entry))
+        .collect();
+
+    let files: Vec<_> = scan_dir(
         &config.source.static_path,
         &config.out.path,
         &config
@@ -173,121 +581,330 @@ pub fn build(verbosity: Verbosity, config: Config) -> Result<(), BuildError> {
             .exclude
             .iter()
             .map(|s| s.as_str())
-            .chain(
-                [
-                    &config.out.path,
-                    &config.source.pages_path,
-                    &config.source.template,
-                    "simple-router.toml",
-                ]
-                .into_iter(),
-            )
+            .chain([
+                &config.out.path,
+                &config.source.pages_path,
+                &config.source.template,
+                &config.source.layouts_path,
+                &config.source.components_path,
+                &config.source.data_path,
+                "simple-router.toml",
+            ])
             .collect(),
-        verbosity,
-    )? {
-        fs::copy(file, out)?;
-    }
+        log,
+    )?
+    .into_iter()
+    .filter(|(file, _)| !asset_entries.contains(file))
+    .collect();
 
-    if verbosity == Verbosity::High {
-        println!("Done!");
+    // Files are hashed first, since fingerprinted names depend on the hash.
+    let checked = parallel_map(jobs, &files, |(file, out)| previous.check(file, out));
+    let mut copies = Vec::new();
+    for ((file, out), checked) in files.into_iter().zip(checked) {
+        let (fresh, record) = checked?;
+        if config.assets.fingerprints(&file) {
+            let hashed = out_path.join(fingerprints.insert(&relative(&out), record.hash()));
+            copies.push((file.clone(), hashed, None));
+        }
+        copies.push((file, out, Some((fresh, record))));
     }
-
-    let template_path = PathBuf::from(&config.source.template);
-    if verbosity == Verbosity::High {
-        print!("Parsing template at {} ", template_path.to_string_lossy());
+    let copied = parallel_map(jobs, &copies, |(file, out, checked)| {
+        let (fresh, record) = match checked {
+            Some(checked) => checked.clone(),
+            None => previous.check(file, out)?,
+        };
+        if !fresh && !plugin::handled(plugins, Hook::StaticFile) {
+            fs::copy(file, out)?;
+        } else if !fresh {
+            let contents = plugin::change_static_file(plugins, file, out, fs::read(file)?)?;
+            fs::write(out, contents)?;
+        }
+        Ok::<_, BuildError>((fresh, record))
+    });
+    for ((_, out, _), copied) in copies.into_iter().zip(copied) {
+        let (fresh, record) = copied?;
+        if fresh {
+            summary.unchanged += 1;
+        } else {
+            summary.copied += 1;
+        }
+        manifest.insert(out, record);
     }
-    let template = Template::parse_from_file(
-        &template_path,
-        config.xml.into(),
-        config.out.lib_file.clone(),
-    )
-    .map_err(|err| {
-        BuildError::from(err).with_source(template_path.to_string_lossy().to_string())
-    })?;
-    if verbosity == Verbosity::High {
-        println!("Done!");
+
+    // Bundles are always rebuilt, since the files they import aren't tracked, but only written
+    // when they change.
+    for (entry, file) in config.assets.entries.iter().zip(&asset_entries) {
+        let out = out_path.join(entry);
+        let bundled = assets::bundle(file, static_path, config.assets.minify).map_err(|msg| {
+            BuildError::Other {
+                msg,
+                source: Some(file.to_string_lossy().to_string()),
+            }
+        })?;
+        let bundled = plugin::change_static_file(plugins, file, &out, bundled.into_bytes())?;
+        if let Some(parent) = out.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut outs = vec![out];
+        if config.assets.fingerprints(file) {
+            let hash = manifest::hash_bytes(&bundled);
+            outs.push(out_path.join(fingerprints.insert(&relative(&outs[0]), hash)));
+        }
+        for out in outs {
+            let (fresh, record) = previous.check_contents(file, &out, &bundled)?;
+            if fresh {
+                summary.unchanged += 1;
+            } else {
+                log.info(&format!("  {} (bundled)", out.to_string_lossy()));
+                fs::write(&out, &bundled)?;
+                summary.copied += 1;
+            }
+            manifest.insert(out, record);
+        }
     }
 
-    if verbosity == Verbosity::High {
-        println!("Generating static site in {} ", config.out.path);
+    let mut library = config.js.get_code().as_bytes().to_vec();
+    library.extend_from_slice(include_bytes!("simple_router.js"));
+    let mut library_files = vec![PathBuf::from(&config.out.lib_file)];
+    if config.assets.fingerprints(&library_files[0]) {
+        let hash = manifest::hash_bytes(&library);
+        library_files.push(fingerprints.insert(&library_files[0], hash));
     }
-    for (page, page_out) in scan_dir(
-        &config.source.pages_path,
-        &config.out.path,
-        &config
-            .source
-            .exclude
-            .iter()
-            .map(|s| s.as_str())
-            .chain(
-                [
-                    &config.out.path,
-                    &config.source.static_path,
-                    &config.source.template,
-                    "simple-router.toml",
-                ]
-                .into_iter(),
-            )
-            .collect(),
-        verbosity,
-    )? {
-        let mut props = HashMap::new();
-        props.insert(
-            "__path".to_string(),
-            vec![XmlEvent::Characters(String::from(
-                page_out
-                    .strip_prefix(&config.out.path)
-                    .unwrap_or(&page_out)
-                    .with_extension("")
-                    .to_string_lossy(),
-            ))],
-        );
+    // Pages have to be rendered again when a file they refer to gets a new fingerprint.
+    let template_hash = manifest::hash_value(&(template_hash, &fingerprints));
+    manifest.template = template_hash;
+
+    log.done(Some(&format!("{} unchanged", summary.unchanged)));
+
+    report.enter(Stage::Template);
+    let pages = list_pages(config)?;
+    let library_path = library_files
+        .last()
+        .unwrap()
+        .to_string_lossy()
+        .replace('\\', "/");
+    let site = load_site(
+        log,
+        config,
+        &pages,
+        fingerprints,
+        library_path,
+        report,
+        plugins,
+    )?;
+    manifest.collections = site.collections_hash;
+
+    report.enter(Stage::Page);
+    let summary = &mut report.summary;
+    log.info(&format!("Generating static site in {}", config.out.path));
+    summary.template_changed = previous.template != template_hash;
+    // Pages listing a collection have to be rendered again when any page in it changes.
+    let collections_changed = previous.collections != site.collections_hash;
+    let template_lists = collections_changed
+        && (site.template.lists_collection()
+            || site.layouts.values().any(Template::lists_collection));
+    let template_changed = summary.template_changed;
+    let rendered = parallel_map(jobs, &pages, |(page, page_out)| {
+        let mut rendered = RenderedPage::default();
+        let json_out = page_out.with_extension("page.json");
+        let (fresh, record) = previous.check(page, page_out)?;
+        let (json_fresh, json_record) = previous.check(page, &json_out)?;
+        rendered.files.push((page_out.clone(), record));
+        rendered.files.push((json_out, json_record));
+        if site.failed.contains(page) {
+            // Its error was reported when collecting it.
+            return Ok(rendered);
+        }
+        if fresh
+            && json_fresh
+            && !template_changed
+            && !template_lists
+            && !(collections_changed
+                && previous
+                    .page(page_out)
+                    .is_none_or(|record| record.lists_collection))
+        {
+            rendered.unchanged = true;
+            return Ok(rendered);
+        }
+
+        rendered.log.push(format!("  {}", page.to_string_lossy()));
 
-        let source = BufReader::new(File::open(page.clone())?);
+        let props = match site.parsed.get(page) {
+            Some(props) => Ok(props.clone()),
+            None => parse_page(
+                config,
+                page,
+                page_out,
+                site.context.highlighter.as_ref(),
+                plugins,
+            ),
+        };
+        let result = props.and_then(|props| {
+            let page_record = page_record(config, &site.context, page, page_out, &props)
+                .map_err(|err| err.with_source(page.to_string_lossy().to_string()))?;
+            render_page(
+                config,
+                &site,
+                page,
+                page_out,
+                props,
+                |template, props, out| {
+                    // Later pages of a paginated listing aren't in the manifest yet.
+                    if out != *page_out {
+                        let json_out = out.with_extension("page.json");
+                        let (_, record) = previous.check(page, &out)?;
+                        let (_, json_record) = previous.check(page, &json_out)?;
+                        rendered.files.push((out.clone(), record));
+                        rendered.files.push((json_out, json_record));
+                    }
 
-        let out_json = BufWriter::new(File::create(page_out.with_extension("page.json"))?);
+                    let props = plugin::change_props(
+                        plugins,
+                        Hook::BeforeRender,
+                        page,
+                        &out,
+                        props,
+                        config.xml.into(),
+                    )?;
+                    let saved = write_page(config, template, props, page, &out, plugins)?;
+                    if config.minify.enabled {
+                        rendered.log.push(format!(
+                            "    {} (saved {saved} bytes)",
+                            out.to_string_lossy()
+                        ));
+                    }
+                    rendered.pages.push((out, page_record.clone()));
+                    Ok(())
+                },
+            )
+        });
+        rendered.error = result.err();
+        Ok::<_, BuildError>(rendered)
+    });
 
-        if verbosity == Verbosity::High {
-            println!("  {}", page.to_string_lossy());
+    // Pages are added to the manifest and logged in order, whichever thread rendered them.
+    let mut unchanged = 0;
+    for ((page, _), rendered) in pages.iter().zip(rendered) {
+        let rendered = rendered?;
+        for line in &rendered.log {
+            log.info(line);
+        }
+        for (out, record) in rendered.files {
+            manifest.insert(out, record);
+        }
+        if rendered.unchanged {
+            // Also keep the other pages of a paginated listing.
+            manifest.retain_from(&previous, page);
+            summary.unchanged += 1;
+            unchanged += 1;
+        }
+        for (out, record) in rendered.pages {
+            summary.pages.push(json_url(config, &out));
+            manifest.insert_page(out, record);
         }
+        if let Some(err) = rendered.error {
+            report.errors.push(err);
+        }
+    }
+    log.done(Some(&format!("{unchanged} unchanged")));
 
-        let is_404 = page_out.ends_with(Path::new(&config.js.not_found));
+    report.enter(Stage::Output);
+    if let Some(feed) = &config.feed {
+        log.start(&format!("Writing feeds for {}", feed.collection));
+        let base_url = base_url(config, "feeds")?;
+        let items = site
+            .context
+            .collections
+            .get(&feed.collection)
+            .ok_or_else(|| {
+                BuildError::from(TemplateError::MissingCollection(feed.collection.clone()))
+                    .with_source(String::from("simple-router.toml"))
+            })?;
+        feed::write_feeds(feed, base_url, items, Path::new(&config.out.path)).map_err(|err| {
+            BuildError::Write {
+                err,
+                source: Some(String::from("feed")),
+            }
+        })?;
+        log.done(None);
+    }
 
-        let mut out = BufWriter::new(File::create(page_out)?);
-        out.write_all(b"<!doctype html>")?;
+    if config.sitemap.enabled {
+        log.start("Writing sitemap");
+        let base_url = base_url(config, "the sitemap")?;
+        let pages = manifest
+            .pages()
+            .filter(|(_, record)| !record.noindex)
+            .map(|(out, record)| (page_url(config, out), record.lastmod.as_str()));
+        sitemap::write_sitemap(
+            config.sitemap.robots,
+            base_url,
+            pages,
+            Path::new(&config.out.path),
+        )
+        .map_err(|err| BuildError::Write {
+            err,
+            source: Some(String::from(sitemap::SITEMAP_FILE)),
+        })?;
+        log.done(None);
+    }
 
-        template
-            .write_to_file(source, out, out_json, props, is_404)
-            .map_err(|err| BuildError::from(err).with_source(page.to_string_lossy().to_string()))?;
+    report.summary.removed = manifest.remove_stale(&previous, &config.out.path)?;
+    if report.summary.removed > 0 {
+        log.info(&format!("Removed {} stale files.", report.summary.removed));
     }
-    if verbosity == Verbosity::High {
-        println!("Done!");
+
+    for library_file in &library_files {
+        let library_path = out_path.join(library_file);
+        log.start(&format!(
+            "Adding library file at {}",
+            library_path.to_string_lossy()
+        ));
+        File::create(library_path)?.write_all(&library)?;
+        log.done(None);
     }
 
-    let mut library_path = PathBuf::from(&config.out.path);
-    library_path.push(&config.out.lib_file);
-    if verbosity == Verbosity::High {
-        print!("Adding library file at {} ", library_path.to_string_lossy());
+    if config.assets.fingerprint {
+        log.start(&format!("Writing {}", assets::MANIFEST_FILE));
+        site.context.fingerprints.write_manifest(out_path)?;
+        log.done(None);
     }
 
-    let mut library = config.js.get_code().as_bytes().to_vec();
-    library.extend_from_slice(include_bytes!("simple_router.js"));
+    if let Some(highlighter) = &site.context.highlighter {
+        let css_path = Path::new(&config.out.path).join(&config.highlight.css_file);
+        log.start(&format!(
+            "Adding highlighting theme at {}",
+            css_path.to_string_lossy()
+        ));
+        fs::write(css_path, highlighter.css())?;
+        log.done(None);
+    }
 
-    File::create(library_path)?.write_all(&library)?;
-    if verbosity == Verbosity::High {
-        println!("Done!");
+    // The manifest was invalidated at the start, so pages that failed are rendered again by the
+    // next build.
+    if !report.errors.is_empty() {
+        return Ok(());
     }
+    manifest.save(&config.out.path)?;
 
-    if let Some(cmd) = config.scripts.postbuild {
-        if verbosity == Verbosity::High {
-            println!("Running post-build script... ");
+    if config.check.links {
+        report.enter(Stage::Links);
+        log.start("Checking links");
+        if !add_broken_links(config, &manifest, report)? {
+            return Ok(());
         }
+        log.done(None);
+    }
+
+    if let Some(cmd) = &config.scripts.postbuild {
+        report.enter(Stage::Script);
+        log.info("Running post-build script...");
 
-        let status = Command::new("sh").args(["-c", &cmd]).status()?;
+        let status = run_script(cmd)?;
         if status.success() {
-            if verbosity == Verbosity::High {
-                println!("Done!");
-            }
+            log.done(None);
         } else {
             return Err(BuildError::Other {
                 msg: format!("Post-build script failed with exit code {status}"),
@@ -299,12 +916,715 @@ pub fn build(verbosity: Verbosity, config: Config) -> Result<(), BuildError> {
     Ok(())
 }
 
+/// Runs a pre- or post-build script. Its output goes to stderr, so that stdout only has the
+/// build's own output, like the JSON report.
+fn run_script(cmd: &str) -> io::Result<std::process::ExitStatus> {
+    Command::new("sh")
+        .args(["-c", cmd])
+        .stdout(io::stderr())
+        .status()
+}
+
+/// Checks the links in the built site, adding the broken ones to the report as errors. Returns
+/// whether every link works.
+fn add_broken_links(
+    config: &Config,
+    manifest: &Manifest,
+    report: &mut BuildReport,
+) -> Result<bool, BuildError> {
+    let broken = links::check_links(config, manifest)?;
+    if broken.is_empty() {
+        return Ok(true);
+    }
+    report.failed = Some(Stage::Links);
+    report
+        .errors
+        .extend(broken.into_iter().map(BuildError::Link));
+    Ok(false)
+}
+
+/// What rendering a page produced, kept to be added to the manifest in order.
+#[derive(Default)]
+struct RenderedPage {
+    /// Output files of the page, with their records.
+    files: Vec<(PathBuf, FileRecord)>,
+    /// Pages that were written, with the record of their source.
+    pages: Vec<(PathBuf, PageRecord)>,
+    /// Whether the page was up to date, so its previous output is kept.
+    unchanged: bool,
+    /// What to log about the page.
+    log: Vec<String>,
+    error: Option<BuildError>,
+}
+
+/// Calls `f` with every item on up to `jobs` threads, returning the results in the order of
+/// `items`.
+fn parallel_map<T: Sync, R: Send>(jobs: usize, items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let jobs = jobs.min(items.len());
+    if jobs <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, R)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(i) else {
+                            return results;
+                        };
+                        results.push((i, f(item)));
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Parses the template, layouts and every page, and renders them without writing anything, to
+/// find every error instead of stopping at the first. With `check_links`, also checks the links in
+/// the site that was already built.
+pub fn check(config: &Config, check_links: bool) -> BuildReport {
+    let mut report = BuildReport::default();
+    let result = check_site(config, check_links, &mut report);
+    report.finish(result)
+}
+
+fn check_site(
+    config: &Config,
+    check_links: bool,
+    report: &mut BuildReport,
+) -> Result<(), BuildError> {
+    report.enter(Stage::Template);
+    let pages = list_pages(config)?;
+    let mut site = load_site(
+        &Verbosity::Silent,
+        config,
+        &pages,
+        Fingerprints::default(),
+        config.out.lib_file.clone(),
+        report,
+        &[],
+    )?;
+
+    report.enter(Stage::Page);
+    if let Some(feed) = &config.feed {
+        if let Err(err) = base_url(config, "feeds") {
+            report.errors.push(err);
+        }
+        if site.context.collections.get(&feed.collection).is_none() {
+            report.errors.push(
+                BuildError::from(TemplateError::MissingCollection(feed.collection.clone()))
+                    .with_source(String::from("simple-router.toml")),
+            );
+        }
+    }
+    if config.sitemap.enabled {
+        if let Err(err) = base_url(config, "the sitemap") {
+            report.errors.push(err);
+        }
+    }
+
+    for (page, page_out) in &pages {
+        if site.failed.contains(page) {
+            continue;
+        }
+        let props = match site.parsed.remove(page) {
+            Some(props) => Ok(props),
+            None => parse_page(
+                config,
+                page,
+                page_out,
+                site.context.highlighter.as_ref(),
+                &[],
+            ),
+        };
+        let summary = &mut report.summary;
+        let result = props.and_then(|props| {
+            render_page(
+                config,
+                &site,
+                page,
+                page_out,
+                props,
+                |template, props, out| {
+                    let is_404 = out.ends_with(Path::new(&config.js.not_found));
+                    template.write_to_file(io::sink(), io::sink(), props, is_404)?;
+                    summary.pages.push(json_url(config, &out));
+                    Ok(())
+                },
+            )
+        });
+        if let Err(err) = result {
+            report.errors.push(err);
+        }
+    }
+
+    if check_links && report.errors.is_empty() {
+        report.enter(Stage::Links);
+        let manifest = Manifest::load(&config.out.path).unwrap_or_default();
+        add_broken_links(config, &manifest, report)?;
+    }
+    Ok(())
+}
+
+/// Loads the template the way a build does, with components, data and collections, to render
+/// pages with [`Template::write_to_file`].
+pub fn load_template(config: &Config) -> Result<Template, BuildError> {
+    let pages = list_pages(config)?;
+    let mut report = BuildReport::default();
+    let site = load_site(
+        &Verbosity::Silent,
+        config,
+        &pages,
+        Fingerprints::default(),
+        config.out.lib_file.clone(),
+        &mut report,
+        &[],
+    )?;
+    match report.errors.into_iter().next() {
+        Some(err) => Err(err),
+        None => Ok(site.template),
+    }
+}
+
+/// Everything pages are rendered with.
+struct Site {
+    context: Arc<TemplateContext>,
+    template: Template,
+    layouts: HashMap<String, Template>,
+    /// Pages that were already parsed for a collection.
+    parsed: HashMap<PathBuf, HashMap<String, Vec<XmlEvent>>>,
+    /// Pages that failed to parse for a collection, already added to the errors.
+    failed: HashSet<PathBuf>,
+    collections_hash: u64,
+}
+
+/// Loads components, data and collections, and parses the template and layouts. Pages that fail to
+/// parse are left out of collections, with their errors added to the report.
+fn load_site(
+    log: &dyn Logger,
+    config: &Config,
+    pages: &[(PathBuf, PathBuf)],
+    fingerprints: Fingerprints,
+    library_path: String,
+    report: &mut BuildReport,
+    plugins: &[&dyn Plugin],
+) -> Result<Site, BuildError> {
+    let template_path = PathBuf::from(&config.source.template);
+    let layout_files = list_files(Path::new(&config.source.layouts_path))?;
+    let component_files = list_files(Path::new(&config.source.components_path))?;
+    let data_files = list_files(Path::new(&config.source.data_path))?;
+
+    let mut components = Components::default();
+    for file in &component_files {
+        components
+            .parse_from_file(
+                relative_name(&config.source.components_path, file),
+                file,
+                config.xml.into(),
+            )
+            .map_err(|err| BuildError::from(err).with_source(file.to_string_lossy().to_string()))?;
+    }
+
+    let mut data = Data::default();
+    for file in &data_files {
+        data.parse_from_file(relative_name(&config.source.data_path, file), file)
+            .map_err(|err| BuildError::from(err).with_source(file.to_string_lossy().to_string()))?;
+    }
+
+    let highlighter = if config.highlight.enabled {
+        Some(
+            Highlighter::new(&config.highlight).map_err(|msg| BuildError::Other {
+                msg,
+                source: Some(String::from("simple-router.toml")),
+            })?,
+        )
+    } else {
+        None
+    };
+
+    let mut parsed = HashMap::new();
+    let mut failed = HashSet::new();
+    let mut collections = Collections::default();
+    let mut collection_hashes = Vec::new();
+    for (name, collection) in &config.collections {
+        log.start(&format!("Collecting {name}"));
+        let pattern = glob::Pattern::new(&collection.path).map_err(|err| BuildError::Other {
+            msg: format!("Invalid path for collection {name}: {err}"),
+            source: Some(String::from("simple-router.toml")),
+        })?;
+
+        let mut items = Vec::new();
+        for (page, page_out) in pages {
+            let relative = page.strip_prefix(&config.source.pages_path).unwrap_or(page);
+            if !pattern.matches_path(relative) {
+                continue;
+            }
+
+            if failed.contains(page) {
+                continue;
+            }
+            if !parsed.contains_key(page) {
+                match parse_page(config, page, page_out, highlighter.as_ref(), plugins) {
+                    Ok(props) => parsed.insert(page.clone(), props),
+                    Err(err) => {
+                        report.errors.push(err);
+                        failed.insert(page.clone());
+                        continue;
+                    }
+                };
+            }
+            let props = parsed[page]
+                .iter()
+                .map(|(prop, events)| Ok((prop.clone(), components.expand(events.clone())?)))
+                .collect::<Result<HashMap<_, _>, TemplateError>>();
+            let mut props = match props {
+                Ok(props) => props,
+                Err(err) => {
+                    report.errors.push(
+                        BuildError::from(err).with_source(page.to_string_lossy().to_string()),
+                    );
+                    parsed.remove(page);
+                    failed.insert(page.clone());
+                    continue;
+                }
+            };
+            props.insert(
+                String::from("__url"),
+                vec![XmlEvent::Characters(format!(
+                    "/{}",
+                    page_out
+                        .strip_prefix(&config.out.path)
+                        .unwrap_or(page_out)
+                        .to_string_lossy()
+                ))],
+            );
+            // Pages left out by the filter are hashed too, in case they change to match it.
+            collection_hashes.push((page.clone(), manifest::hash_file(page)?));
+            if collection.matches(|prop| xml::prop_text(&props, prop)) {
+                items.push(props);
+            }
+        }
+
+        if items.is_empty() {
+            report.warnings.push(format!(
+                "Collection '{name}' doesn't match any pages in simple-router.toml"
+            ));
+        }
+        collections.insert(
+            name.clone(),
+            items,
+            collection.sort_by.as_deref(),
+            collection.reverse,
+        );
+        log.done(None);
+    }
+    let collections_hash = manifest::hash_value(&collection_hashes);
+
+    log.start(&format!(
+        "Parsing template at {}",
+        template_path.to_string_lossy()
+    ));
+    let context = Arc::new(TemplateContext {
+        parser_config: config.xml.into(),
+        library_path,
+        components,
+        collections,
+        data,
+        highlighter,
+        fingerprints,
+        minify: config.minify.enabled,
+    });
+    let template = Template::parse_from_file(&template_path, context.clone()).map_err(|err| {
+        BuildError::from(err).with_source(template_path.to_string_lossy().to_string())
+    })?;
+    log.done(None);
+
+    let mut layouts = HashMap::new();
+    for file in &layout_files {
+        let name = relative_name(&config.source.layouts_path, file);
+        log.start(&format!("Parsing layout {name}"));
+        load_layout(name, config, &template, &mut layouts, &mut Vec::new())?;
+        log.done(None);
+    }
+
+    Ok(Site {
+        context,
+        template,
+        layouts,
+        parsed,
+        failed,
+        collections_hash,
+    })
+}
+
+/// Lists the pages in the pages directory, with the file each is written to.
+fn list_pages(config: &Config) -> io::Result<Vec<(PathBuf, PathBuf)>> {
+    let exclude: Vec<&str> = config
+        .source
+        .exclude
+        .iter()
+        .map(|s| s.as_str())
+        .chain([
+            &config.out.path,
+            &config.source.static_path,
+            &config.source.template,
+            &config.source.layouts_path,
+            &config.source.components_path,
+            &config.source.data_path,
+            "simple-router.toml",
+        ])
+        .collect();
+
+    Ok(list_files(Path::new(&config.source.pages_path))?
+        .into_iter()
+        .filter(|page| !is_excluded(page, &exclude))
+        .map(|page| {
+            let mut page_out = Path::new(&config.out.path).join(
+                page.strip_prefix(&config.source.pages_path)
+                    .unwrap_or(&page),
+            );
+            if page.extension() == Some(OsStr::new("md")) {
+                page_out.set_extension("html");
+            }
+            (page, page_out)
+        })
+        .collect())
+}
+
+/// Renders a page with its layout, calling `write` with the template, properties and output path
+/// of the page, or of every page of a paginated listing.
+fn render_page(
+    config: &Config,
+    site: &Site,
+    page: &Path,
+    page_out: &Path,
+    mut props: HashMap<String, Vec<XmlEvent>>,
+    mut write: impl FnMut(&Template, HashMap<String, Vec<XmlEvent>>, PathBuf) -> Result<(), BuildError>,
+) -> Result<(), BuildError> {
+    let source = || page.to_string_lossy().to_string();
+    let template = match props.remove("layout") {
+        Some(layout) => {
+            let layout = xml::text_content(&layout).trim().to_owned();
+            site.layouts.get(&layout).ok_or_else(|| {
+                BuildError::Other {
+                    msg: format!("Unknown layout '{layout}'."),
+                    source: None,
+                }
+                .with_source(source())
+            })?
+        }
+        None => &site.template,
+    };
+
+    let Some(paginate) = props.remove("paginate") else {
+        return write(template, props, page_out.to_path_buf())
+            .map_err(|err| err.with_source(source()));
+    };
+
+    let collection = xml::text_content(&paginate).trim().to_owned();
+    let (Some(items), Some(collection_config)) = (
+        site.context.collections.get(&collection),
+        config.collections.get(&collection),
+    ) else {
+        return Err(
+            BuildError::from(TemplateError::MissingCollection(collection)).with_source(source()),
+        );
+    };
+    let per_page = collection_config.per_page.max(1);
+    let page_count = items.len().div_ceil(per_page).max(1);
+    let url = |number| page_url(config, &paginated_path(page_out, number));
+
+    for number in 1..=page_count {
+        let page_out = paginated_path(page_out, number);
+        let mut props = props.clone();
+        let range = (number - 1) * per_page..number * per_page;
+        for events in props.values_mut() {
+            *events = site
+                .context
+                .components
+                .expand(events.clone())
+                .and_then(|events| {
+                    site.context
+                        .collections
+                        .expand_page(events, Some((&collection, range.clone())))
+                })
+                .map_err(|err| BuildError::from(err).with_source(source()))?;
+        }
+        // The first page has no `__prev`, and the last page no `__next`.
+        let prev = (number > 1).then(|| ("__prev", url(number - 1)));
+        let next = (number < page_count).then(|| ("__next", url(number + 1)));
+        let path = page_out
+            .strip_prefix(&config.out.path)
+            .unwrap_or(&page_out)
+            .with_extension("")
+            .to_string_lossy()
+            .to_string();
+        for (prop, value) in [
+            ("__path", path),
+            ("__page_number", number.to_string()),
+            ("__page_count", page_count.to_string()),
+        ]
+        .into_iter()
+        .chain(prev)
+        .chain(next)
+        {
+            props.insert(prop.to_owned(), vec![XmlEvent::Characters(value)]);
+        }
+
+        write(template, props, page_out).map_err(|err| err.with_source(source()))?;
+    }
+
+    Ok(())
+}
+
+/// Writes a page and its `.page.json`, after `plugins` change them. Returns the number of bytes
+/// saved by minifying it.
+fn write_page(
+    config: &Config,
+    template: &Template,
+    props: HashMap<String, Vec<XmlEvent>>,
+    page: &Path,
+    page_out: &Path,
+    plugins: &[&dyn Plugin],
+) -> Result<usize, BuildError> {
+    let is_404 = page_out.ends_with(Path::new(&config.js.not_found));
+
+    let mut html = b"<!doctype html>".to_vec();
+    let mut json = Vec::new();
+    let saved = template.write_to_file(&mut html, &mut json, props, is_404)?;
+
+    if plugin::handled(plugins, Hook::AfterRender) {
+        let mut rendered = plugin::RenderedPage {
+            source: page.to_path_buf(),
+            out: page_out.to_path_buf(),
+            html: String::from_utf8_lossy(&html).into_owned(),
+            json: String::from_utf8_lossy(&json).into_owned(),
+        };
+        for plugin in plugins
+            .iter()
+            .filter(|plugin| plugin.handles(Hook::AfterRender))
+        {
+            plugin
+                .after_render(&mut rendered)
+                .map_err(|msg| plugin::plugin_error(*plugin, msg, Some(page)))?;
+        }
+        html = rendered.html.into_bytes();
+        json = rendered.json.into_bytes();
+    }
+
+    fs::create_dir_all(page_out.parent().unwrap_or(Path::new(&config.out.path)))?;
+    fs::write(page_out.with_extension("page.json"), json)?;
+    fs::write(page_out, html)?;
+    Ok(saved)
+}
+
+/// The URL of the `.page.json` file of a page, e.g. `/cat.page.json`.
+fn json_url(config: &Config, page_out: &Path) -> String {
+    let json_out = page_out.with_extension("page.json");
+    format!(
+        "/{}",
+        json_out
+            .strip_prefix(&config.out.path)
+            .unwrap_or(&json_out)
+            .to_string_lossy()
+    )
+}
+
+/// The URL a page is served at, relative to the site: `posts/index.html` is served at `/posts/`.
+fn page_url(config: &Config, page_out: &Path) -> String {
+    let url = page_out.strip_prefix(&config.out.path).unwrap_or(page_out);
+    if url.file_name() == Some(OsStr::new("index.html")) {
+        format!("/{}/", url.parent().unwrap_or(url).to_string_lossy()).replace("//", "/")
+    } else {
+        format!("/{}", url.to_string_lossy())
+    }
+}
+
+/// Records the last modification date of a page, taken from the `lastmod_prop` property or else
+/// the source file, and whether it is left out of the sitemap.
+fn page_record(
+    config: &Config,
+    context: &TemplateContext,
+    page: &Path,
+    page_out: &Path,
+    props: &HashMap<String, Vec<XmlEvent>>,
+) -> Result<PageRecord, BuildError> {
+    let lastmod = match xml::prop_text(props, &config.sitemap.lastmod_prop)
+        .and_then(|text| Date::parse(&text))
+    {
+        Some(date) => date,
+        None => Date::from_system_time(fs::metadata(page)?.modified()?),
+    };
+
+    Ok(PageRecord {
+        lastmod: lastmod.to_w3c_date(),
+        noindex: props.contains_key("noindex")
+            || page_out.ends_with(Path::new(&config.js.not_found)),
+        lists_collection: props.contains_key("paginate")
+            || props
+                .values()
+                .map(|events| context.lists_collection(events))
+                .collect::<Result<Vec<_>, _>>()?
+                .contains(&true),
+    })
+}
+
+fn base_url<'a>(config: &'a Config, needed_for: &str) -> Result<&'a str, BuildError> {
+    config.base_url.as_deref().ok_or_else(|| BuildError::Other {
+        msg: format!("base_url is required to write {needed_for}."),
+        source: Some(String::from("simple-router.toml")),
+    })
+}
+
+/// Where page `number` of a paginated page is written: `posts/index.html` is followed by
+/// `posts/page/2/index.html`, and `blog.html` by `blog/page/2/index.html`.
+fn paginated_path(page_out: &Path, number: usize) -> PathBuf {
+    if number == 1 {
+        return page_out.to_path_buf();
+    }
+
+    let dir = if page_out.file_stem() == Some(OsStr::new("index")) {
+        page_out.parent().unwrap_or(page_out).to_path_buf()
+    } else {
+        page_out.with_extension("")
+    };
+    dir.join("page").join(number.to_string()).join("index.html")
+}
+
+/// Parses the properties of a page, including `__path`.
+fn parse_page(
+    config: &Config,
+    page: &Path,
+    page_out: &Path,
+    highlighter: Option<&Highlighter>,
+    plugins: &[&dyn Plugin],
+) -> Result<HashMap<String, Vec<XmlEvent>>, BuildError> {
+    let props = if page.extension() == Some(OsStr::new("md")) {
+        markdown::parse_page(&fs::read_to_string(page)?, config.xml.into(), highlighter)
+    } else {
+        xml::parse_page(BufReader::new(File::open(page)?), config.xml.into())
+    };
+    let mut props = props
+        .map_err(|err| BuildError::from(err).with_source(page.to_string_lossy().to_string()))?;
+    props.insert(
+        "__path".to_string(),
+        vec![XmlEvent::Characters(String::from(
+            page_out
+                .strip_prefix(&config.out.path)
+                .unwrap_or(page_out)
+                .with_extension("")
+                .to_string_lossy(),
+        ))],
+    );
+
+    plugin::change_props(
+        plugins,
+        Hook::PageParsed,
+        page,
+        page_out,
+        props,
+        config.xml.into(),
+    )
+}
+
+/// The name of a layout or component: its path relative to `dir`, without extension.
+fn relative_name(dir: &str, path: &Path) -> String {
+    path.strip_prefix(dir)
+        .unwrap_or(path)
+        .with_extension("")
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// Parses the layout `name` and the layouts it extends into `layouts`. `stack` holds the layouts
+/// currently being loaded, to detect cycles.
+fn load_layout(
+    name: String,
+    config: &Config,
+    template: &Template,
+    layouts: &mut HashMap<String, Template>,
+    stack: &mut Vec<String>,
+) -> Result<(), BuildError> {
+    if layouts.contains_key(&name) {
+        return Ok(());
+    }
+
+    let path = Path::new(&config.source.layouts_path).join(format!("{name}.html"));
+    let source = path.to_string_lossy().to_string();
+    if stack.contains(&name) {
+        stack.push(name);
+        return Err(BuildError::Other {
+            msg: format!("Layouts extend each other: {}.", stack.join(" -> ")),
+            source: Some(source),
+        });
+    }
+
+    let file =
+        File::open(&path).map_err(|err| BuildError::from(err).with_source(source.clone()))?;
+    let mut props = xml::parse_page(BufReader::new(file), config.xml.into())
+        .map_err(|err| BuildError::from(err).with_source(source.clone()))?;
+
+    let layout = match props.remove("layout") {
+        Some(parent) => {
+            let parent = xml::text_content(&parent).trim().to_owned();
+            if !Path::new(&config.source.layouts_path)
+                .join(format!("{parent}.html"))
+                .is_file()
+            {
+                return Err(BuildError::Other {
+                    msg: format!("Unknown layout '{parent}'."),
+                    source: Some(source),
+                });
+            }
+            stack.push(name.clone());
+            load_layout(parent.clone(), config, template, layouts, stack)?;
+            stack.pop();
+            layouts[&parent].extend(name.clone(), props)
+        }
+        None => template.extend(name.clone(), props),
+    }
+    .map_err(|err| BuildError::from(err).with_source(source))?;
+
+    layouts.insert(name, layout);
+    Ok(())
+}
+
+pub fn list_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if !dir.is_dir() {
+        return Ok(files);
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.append(&mut list_files(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
 // From StackOverflow: https://stackoverflow.com/a/65192210 + modifications
 fn scan_dir(
     src: impl AsRef<Path>,
     dst: impl AsRef<Path>,
     exclude: &Vec<&str>,
-    verbosity: Verbosity,
+    log: &dyn Logger,
 ) -> io::Result<Vec<(PathBuf, PathBuf)>> {
     let mut entries = Vec::new();
     fs::create_dir_all(dst.as_ref())?;
@@ -313,10 +1633,7 @@ fn scan_dir(
         let entry = entry?;
         let ty = entry.file_type()?;
 
-        if exclude
-            .iter()
-            .any(|d| entry.path().starts_with(String::from("./") + d))
-        {
+        if is_excluded(&entry.path(), exclude) {
             continue;
         }
 
@@ -325,18 +1642,22 @@ fn scan_dir(
                 entry.path(),
                 dst.as_ref().join(entry.file_name()),
                 exclude,
-                verbosity,
+                log,
             )?);
         } else {
-            if verbosity == Verbosity::High {
-                println!(
-                    "  {}",
-                    dst.as_ref().join(entry.file_name()).to_string_lossy()
-                );
-            }
+            log.info(&format!(
+                "  {}",
+                dst.as_ref().join(entry.file_name()).to_string_lossy()
+            ));
             entries.push((entry.path(), dst.as_ref().join(entry.file_name())));
         }
     }
 
     Ok(entries)
 }
+
+fn is_excluded(path: &Path, exclude: &[&str]) -> bool {
+    exclude
+        .iter()
+        .any(|d| path.starts_with(String::from("./") + d))
+}
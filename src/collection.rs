@@ -0,0 +1,219 @@
+use std::{cmp::Ordering, collections::HashMap, ops::Range};
+
+use xml::{attribute::OwnedAttribute, name::OwnedName, reader::XmlEvent};
+
+use crate::xml::{escape_attribute, prop_text, take_children, TemplateError};
+
+/// Lists of pages, used with `<ul sr-collection="posts">`.
+///
+/// The children of an element with `sr-collection` are repeated for every page in the
+/// collection (at most `sr-limit` times). Inside, an element with `sr-item="title"` has its content
+/// replaced by the page's `title` property, and `sr-item-href="__url"` sets the `href` attribute to
+/// the text of the page's `__url` property.
+#[derive(Default)]
+pub struct Collections {
+    collections: HashMap<String, Vec<HashMap<String, Vec<XmlEvent>>>>,
+}
+
+impl Collections {
+    const ATTRIBUTE: &'static str = "sr-collection";
+    const LIMIT: &'static str = "sr-limit";
+    const ITEM: &'static str = "sr-item";
+
+    /// Adds a collection of pages, each given as its properties, sorted by the text of the
+    /// `sort_by` property. Pages without it come last.
+    pub fn insert(
+        &mut self,
+        name: String,
+        mut items: Vec<HashMap<String, Vec<XmlEvent>>>,
+        sort_by: Option<&str>,
+        reverse: bool,
+    ) {
+        let key = |props: &HashMap<String, Vec<XmlEvent>>, prop: &str| prop_text(props, prop);
+        items.sort_by(|a, b| {
+            let order = match sort_by {
+                Some(sort_by) => match (key(a, sort_by), key(b, sort_by)) {
+                    (Some(a), Some(b)) if reverse => b.cmp(&a),
+                    (Some(a), Some(b)) => a.cmp(&b),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                },
+                None => Ordering::Equal,
+            };
+            order.then_with(|| key(a, "__path").cmp(&key(b, "__path")))
+        });
+        if sort_by.is_none() && reverse {
+            items.reverse();
+        }
+
+        self.collections.insert(name, items);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[HashMap<String, Vec<XmlEvent>>]> {
+        self.collections.get(name).map(Vec::as_slice)
+    }
+
+    /// Whether `events` have an `sr-collection` element.
+    pub fn lists(events: &[XmlEvent]) -> bool {
+        events.iter().any(|event| {
+            matches!(event, XmlEvent::StartElement { attributes, .. }
+                if attributes.iter().any(|a| a.name.local_name == Self::ATTRIBUTE))
+        })
+    }
+
+    /// Repeats the contents of every `sr-collection` element in `events` for each of its pages.
+    pub fn expand(&self, events: Vec<XmlEvent>) -> Result<Vec<XmlEvent>, TemplateError> {
+        self.expand_page(events, None)
+    }
+
+    /// Like [`Collections::expand`], but a paginated collection only lists the pages in `range`.
+    pub fn expand_page(
+        &self,
+        events: Vec<XmlEvent>,
+        paginated: Option<(&str, Range<usize>)>,
+    ) -> Result<Vec<XmlEvent>, TemplateError> {
+        let mut expanded = Vec::new();
+        let mut events = events.into_iter();
+
+        while let Some(event) = events.next() {
+            match event {
+                XmlEvent::StartElement {
+                    name,
+                    attributes,
+                    namespace,
+                } if attributes
+                    .iter()
+                    .any(|a| a.name.local_name == Self::ATTRIBUTE) =>
+                {
+                    let mut collection = String::new();
+                    let mut limit = usize::MAX;
+                    let mut kept = Vec::new();
+                    for attribute in attributes {
+                        match attribute.name.local_name.as_str() {
+                            Self::ATTRIBUTE => collection = attribute.value,
+                            Self::LIMIT => {
+                                limit = attribute.value.trim().parse().map_err(|_| {
+                                    TemplateError::MalformedAttribute(Self::LIMIT.to_owned())
+                                })?
+                            }
+                            _ => kept.push(attribute),
+                        }
+                    }
+
+                    let mut items = self
+                        .get(&collection)
+                        .ok_or_else(|| TemplateError::MissingCollection(collection.clone()))?;
+                    if let Some((_, range)) = paginated.as_ref().filter(|(p, _)| *p == collection) {
+                        items = &items[range.start.min(items.len())..range.end.min(items.len())];
+                    }
+                    let fragment = take_children(&mut events);
+
+                    expanded.push(XmlEvent::StartElement {
+                        name: name.clone(),
+                        attributes: kept,
+                        namespace,
+                    });
+                    for item in items.iter().take(limit) {
+                        expanded.append(&mut fill_item(&fragment, item));
+                    }
+                    expanded.push(XmlEvent::EndElement { name });
+                }
+                event => expanded.push(event),
+            }
+        }
+
+        Ok(expanded)
+    }
+}
+
+fn fill_item(fragment: &[XmlEvent], item: &HashMap<String, Vec<XmlEvent>>) -> Vec<XmlEvent> {
+    let mut filled = Vec::new();
+    let mut events = fragment.iter().cloned();
+
+    while let Some(event) = events.next() {
+        match event {
+            XmlEvent::StartElement {
+                name,
+                attributes,
+                namespace,
+            } if attributes
+                .iter()
+                .any(|a| a.name.local_name.starts_with(Collections::ITEM)) =>
+            {
+                let mut content = None;
+                let mut kept = Vec::new();
+                for attribute in attributes {
+                    let local_name = &attribute.name.local_name;
+                    if local_name == Collections::ITEM {
+                        content = Some(attribute.value);
+                    } else if let Some(bound) = local_name
+                        .strip_prefix(Collections::ITEM)
+                        .and_then(|n| n.strip_prefix('-'))
+                    {
+                        let value = prop_text(item, &attribute.value).unwrap_or_default();
+                        kept.push(OwnedAttribute::new(
+                            OwnedName::local(bound),
+                            escape_attribute(&value),
+                        ));
+                    } else {
+                        kept.push(attribute);
+                    }
+                }
+
+                filled.push(XmlEvent::StartElement {
+                    name: name.clone(),
+                    attributes: kept,
+                    namespace,
+                });
+                if let Some(prop) = content {
+                    let fallback = take_children(&mut events);
+                    match item.get(&prop) {
+                        Some(value) => filled.extend(value.iter().cloned()),
+                        None => filled.extend(fallback),
+                    }
+                    filled.push(XmlEvent::EndElement { name });
+                }
+            }
+            event => filled.push(event),
+        }
+    }
+
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use xml::ParserConfig;
+
+    use super::*;
+    use crate::xml::parse_page;
+
+    #[test]
+    fn escapes_item_attributes() {
+        let item = parse_page(
+            "<title>Say \"hi\" &amp; bye</title>".as_bytes(),
+            ParserConfig::default(),
+        )
+        .unwrap();
+        let mut collections = Collections::default();
+        collections.insert(String::from("posts"), vec![item], None, false);
+
+        let events = parse_page(
+            "<list><ul sr-collection=\"posts\"><li sr-item-title=\"title\" /></ul></list>"
+                .as_bytes(),
+            ParserConfig::default(),
+        )
+        .unwrap()
+        .remove("list")
+        .unwrap();
+        let expanded = collections.expand(events).unwrap();
+        let title = expanded.iter().find_map(|event| match event {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } if name.local_name == "li" => Some(attributes[0].value.clone()),
+            _ => None,
+        });
+        assert_eq!(title.as_deref(), Some("Say &quot;hi&quot; &amp; bye"));
+    }
+}
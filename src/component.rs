@@ -0,0 +1,146 @@
+use std::{collections::HashMap, fs::File, io::BufReader, path::Path};
+
+use xml::{reader::XmlEvent, EventReader, ParserConfig};
+
+use crate::xml::{escape_text, take_children, TemplateError};
+
+/// Reusable fragments of markup, used with `<sr-component name="card" title="...">`. Components
+/// with a hyphen in their name can also be used as custom elements, like `<my-card title="...">`.
+///
+/// Inside a component, an element with `sr-slot="title"` has its content replaced by the `title`
+/// attribute of the `sr-component` element, and `sr-slot="children"` by its children. If the
+/// slot isn't given, the element's own content is kept.
+#[derive(Default)]
+pub struct Components {
+    components: HashMap<String, Vec<XmlEvent>>,
+}
+
+impl Components {
+    const ELEMENT: &'static str = "sr-component";
+    const CHILDREN: &'static str = "children";
+
+    pub fn parse_from_file(
+        &mut self,
+        name: String,
+        path: &Path,
+        parser_config: ParserConfig,
+    ) -> Result<(), TemplateError> {
+        let file = BufReader::new(File::open(path)?);
+        let events = EventReader::new_with_config(file, parser_config)
+            .into_iter()
+            .filter(|event| {
+                !matches!(
+                    event,
+                    Ok(XmlEvent::StartDocument { .. } | XmlEvent::EndDocument)
+                )
+            })
+            .collect::<Result<_, _>>()?;
+
+        self.components.insert(name, events);
+        Ok(())
+    }
+
+    /// Replaces every component in `events` with its contents.
+    pub fn expand(&self, events: Vec<XmlEvent>) -> Result<Vec<XmlEvent>, TemplateError> {
+        self.expand_with(events, &mut Vec::new())
+    }
+
+    fn expand_with(
+        &self,
+        events: Vec<XmlEvent>,
+        stack: &mut Vec<String>,
+    ) -> Result<Vec<XmlEvent>, TemplateError> {
+        let mut expanded = Vec::new();
+        let mut events = events.into_iter();
+
+        while let Some(event) = events.next() {
+            match event {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } if name.local_name == Self::ELEMENT
+                    || self.is_custom_element(&name.local_name) =>
+                {
+                    let children = self.expand_with(take_children(&mut events), stack)?;
+
+                    let custom = name.local_name != Self::ELEMENT;
+                    let mut component = custom.then_some(name.local_name);
+                    let mut props = HashMap::new();
+                    for attribute in attributes {
+                        if !custom && attribute.name.local_name == "name" {
+                            component = Some(attribute.value);
+                        } else {
+                            props.insert(attribute.name.local_name, attribute.value);
+                        }
+                    }
+                    let component = component.unwrap_or_default();
+
+                    if stack.contains(&component) {
+                        stack.push(component);
+                        return Err(TemplateError::ComponentCycle(stack.clone()));
+                    }
+                    let body = self
+                        .components
+                        .get(&component)
+                        .ok_or_else(|| TemplateError::MissingComponent(component.clone()))?;
+
+                    stack.push(component);
+                    expanded
+                        .append(&mut self.expand_with(fill_slots(body, &props, &children), stack)?);
+                    stack.pop();
+                }
+                event => expanded.push(event),
+            }
+        }
+
+        Ok(expanded)
+    }
+
+    /// Whether `name` is a component used as a custom element. Custom element names need a
+    /// hyphen, so they can't be confused with HTML elements.
+    fn is_custom_element(&self, name: &str) -> bool {
+        name.contains('-') && self.components.contains_key(name)
+    }
+}
+
+fn fill_slots(
+    body: &[XmlEvent],
+    props: &HashMap<String, String>,
+    children: &[XmlEvent],
+) -> Vec<XmlEvent> {
+    let mut filled = Vec::new();
+    let mut events = body.iter().cloned();
+
+    while let Some(event) = events.next() {
+        match event {
+            XmlEvent::StartElement {
+                name,
+                mut attributes,
+                namespace,
+            } if attributes.iter().any(|a| a.name.local_name == "sr-slot") => {
+                let slot_index = attributes
+                    .iter()
+                    .position(|a| a.name.local_name == "sr-slot")
+                    .unwrap();
+                let slot = attributes.remove(slot_index).value;
+                let fallback = take_children(&mut events);
+
+                filled.push(XmlEvent::StartElement {
+                    name: name.clone(),
+                    attributes,
+                    namespace,
+                });
+                if slot == Components::CHILDREN && !children.is_empty() {
+                    filled.extend(children.iter().cloned());
+                } else if let Some(value) = props.get(&slot) {
+                    filled.push(XmlEvent::Characters(escape_text(value)));
+                } else {
+                    filled.extend(fallback);
+                }
+                filled.push(XmlEvent::EndElement { name });
+            }
+            event => filled.push(event),
+        }
+    }
+
+    filled
+}
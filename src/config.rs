@@ -1,9 +1,15 @@
+use std::{collections::BTreeMap, ffi::OsStr, fs, path::Path};
+
 use serde::Deserialize;
 use xml::ParserConfig;
 
-#[derive(Deserialize, Clone)]
+use crate::plugin::Hook;
+
+#[derive(Deserialize, Clone, Hash)]
 pub struct Config {
     pub library_version: String,
+    #[serde(default)]
+    pub base_url: Option<String>,
     pub out: OutConfig,
     #[serde(default)]
     pub source: SourceConfig,
@@ -13,14 +19,59 @@ pub struct Config {
     pub js: JsConfig,
     #[serde(default)]
     pub scripts: ScriptsConfig,
+    #[serde(default)]
+    pub highlight: HighlightConfig,
+    #[serde(default)]
+    pub collections: BTreeMap<String, CollectionConfig>,
+    #[serde(default)]
+    pub feed: Option<FeedConfig>,
+    #[serde(default)]
+    pub sitemap: SitemapConfig,
+    #[serde(default)]
+    pub minify: MinifyConfig,
+    #[serde(default)]
+    pub assets: AssetsConfig,
+    #[serde(default)]
+    pub check: CheckConfig,
+    #[serde(default)]
+    pub plugins: Vec<PluginConfig>,
 }
 
-#[derive(Deserialize, Clone)]
+impl Config {
+    /// Reads the config at `path`, usually `simple-router.toml`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|_| format!("No file found at {}.", path.to_string_lossy()))?;
+        Self::parse(&text)
+    }
+
+    /// Parses a config, checking that it's for this version of simple router.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(text).map_err(|e| e.message().to_owned())?;
+
+        let version = env!("CARGO_PKG_VERSION");
+        let major_version = version.split(".").take(2).collect::<Vec<_>>().join(".");
+        if config.library_version != major_version {
+            Err(format!(
+                "Incorrect config version. Using version {version}, but config is on {conf}.",
+                conf = config.library_version
+            ))
+        } else {
+            Ok(config)
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Hash)]
 #[serde(default)]
 pub struct SourceConfig {
     pub static_path: String,
     pub pages_path: String,
     pub template: String,
+    pub layouts_path: String,
+    pub components_path: String,
+    pub data_path: String,
     pub exclude: Vec<String>,
 }
 
@@ -30,12 +81,15 @@ impl Default for SourceConfig {
             static_path: String::from("."),
             pages_path: String::from("pages"),
             template: String::from("layout.html"),
+            layouts_path: String::from("layouts"),
+            components_path: String::from("components"),
+            data_path: String::from("data"),
             exclude: Vec::new(),
         }
     }
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, Hash)]
 pub struct OutConfig {
     pub path: String,
     #[serde(default = "default_js_lib_path")]
@@ -46,7 +100,7 @@ fn default_js_lib_path() -> String {
     String::from("simple-router.js")
 }
 
-#[derive(Deserialize, Clone, Copy)]
+#[derive(Deserialize, Clone, Copy, Hash)]
 #[serde(default)]
 pub struct XmlConfig {
     pub ignore_comments: bool,
@@ -69,7 +123,7 @@ impl From<XmlConfig> for ParserConfig {
     }
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, Hash)]
 #[serde(default)]
 pub struct JsConfig {
     pub update_anchors: bool,
@@ -103,9 +157,172 @@ impl Default for JsConfig {
     }
 }
 
-#[derive(Deserialize, Default, Clone)]
+#[derive(Deserialize, Default, Clone, Hash)]
 #[serde(default)]
 pub struct ScriptsConfig {
     pub prebuild: Option<String>,
     pub postbuild: Option<String>,
 }
+
+#[derive(Deserialize, Clone, Hash)]
+#[serde(default)]
+pub struct HighlightConfig {
+    pub enabled: bool,
+    pub theme: String,
+    pub css_file: String,
+}
+
+impl Default for HighlightConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            theme: String::from("InspiredGitHub"),
+            css_file: String::from("highlight.css"),
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Hash)]
+#[serde(default)]
+pub struct MinifyConfig {
+    pub enabled: bool,
+}
+
+#[derive(Deserialize, Clone, Hash)]
+#[serde(default)]
+pub struct AssetsConfig {
+    pub entries: Vec<String>,
+    pub minify: bool,
+    pub fingerprint: bool,
+    pub fingerprint_extensions: Vec<String>,
+}
+
+impl AssetsConfig {
+    /// Whether the file at `path` gets a fingerprinted copy.
+    pub fn fingerprints(&self, path: &Path) -> bool {
+        self.fingerprint
+            && path
+                .extension()
+                .and_then(OsStr::to_str)
+                .is_some_and(|ext| self.fingerprint_extensions.iter().any(|e| e == ext))
+    }
+}
+
+impl Default for AssetsConfig {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            minify: true,
+            fingerprint: false,
+            fingerprint_extensions: [
+                "css", "js", "mjs", "png", "jpg", "jpeg", "gif", "svg", "webp", "avif", "woff",
+                "woff2",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Hash)]
+#[serde(default)]
+pub struct CheckConfig {
+    pub links: bool,
+}
+
+#[derive(Deserialize, Clone, Hash)]
+#[serde(default)]
+pub struct SitemapConfig {
+    pub enabled: bool,
+    pub lastmod_prop: String,
+    pub robots: bool,
+}
+
+impl Default for SitemapConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            lastmod_prop: String::from("lastmod"),
+            robots: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Hash)]
+pub struct CollectionConfig {
+    pub path: String,
+    #[serde(default)]
+    pub sort_by: Option<String>,
+    #[serde(default)]
+    pub reverse: bool,
+    #[serde(default = "default_per_page")]
+    pub per_page: usize,
+    /// Properties a page needs to be in the collection, like `{ tags = "rust" }`.
+    #[serde(default)]
+    pub filter: BTreeMap<String, String>,
+}
+
+impl CollectionConfig {
+    /// Whether a page with these properties (given as their text) is in the collection. A
+    /// property matches if it's the value, or if it's a comma-separated list that includes it.
+    pub fn matches(&self, prop_text: impl Fn(&str) -> Option<String>) -> bool {
+        self.filter.iter().all(|(prop, value)| {
+            prop_text(prop).is_some_and(|text| text.split(',').any(|item| item.trim() == value))
+        })
+    }
+}
+
+fn default_per_page() -> usize {
+    10
+}
+
+#[derive(Deserialize, Clone, Hash)]
+pub struct FeedConfig {
+    pub collection: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default = "default_true")]
+    pub atom: bool,
+    #[serde(default)]
+    pub rss: bool,
+    #[serde(default = "default_feed_limit")]
+    pub limit: usize,
+    #[serde(default = "default_title_prop")]
+    pub title_prop: String,
+    #[serde(default = "default_date_prop")]
+    pub date_prop: String,
+    #[serde(default = "default_summary_prop")]
+    pub summary_prop: String,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_feed_limit() -> usize {
+    20
+}
+
+fn default_title_prop() -> String {
+    String::from("title")
+}
+
+fn default_date_prop() -> String {
+    String::from("date")
+}
+
+fn default_summary_prop() -> String {
+    String::from("summary")
+}
+
+/// An external plugin: a command run for each of its hooks, with JSON on stdin and stdout.
+#[derive(Deserialize, Clone, Hash)]
+pub struct PluginConfig {
+    pub command: String,
+    pub hooks: Vec<Hook>,
+    /// Extensions of the static files the plugin is called with. Empty for every text file.
+    #[serde(default)]
+    pub extensions: Vec<String>,
+}
@@ -0,0 +1,230 @@
+use std::{fs, path::Path};
+
+use serde_json::{Map, Value};
+use xml::{attribute::OwnedAttribute, name::OwnedName, reader::XmlEvent};
+
+use crate::xml::{escape_attribute, escape_text, take_children, TemplateError};
+
+/// Site data loaded from JSON, TOML and YAML files, used with `<span sr-text="data.site.author" />`.
+///
+/// Each file is available under its path without extension, so `data/site.toml` is `data.site`
+/// and `data/authors/jane.json` is `data.authors.jane`. Array elements are reached by index, as in
+/// `data.nav.0.title`, or repeated with `<li sr-for="item in data.nav">`, inside which
+/// `sr-text="item.title"` and `sr-attr-href="item.url"` name the current element.
+#[derive(Default)]
+pub struct Data {
+    root: Map<String, Value>,
+}
+
+impl Data {
+    const TEXT: &'static str = "sr-text";
+    const ATTR: &'static str = "sr-attr-";
+    const FOR: &'static str = "sr-for";
+    const ROOT: &'static str = "data";
+
+    pub fn parse_from_file(&mut self, name: String, path: &Path) -> Result<(), TemplateError> {
+        let source = fs::read_to_string(path)?;
+        let value = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&source).map_err(|err| err.to_string()),
+            Some("toml") => toml::from_str(&source).map_err(|err| err.to_string()),
+            Some("yaml" | "yml") => serde_yaml::from_str(&source).map_err(|err| err.to_string()),
+            _ => return Ok(()),
+        }
+        .map_err(TemplateError::MalformedData)?;
+        self.insert(&name, value);
+        Ok(())
+    }
+
+    /// Adds `value` at `name`, a path like `blog/authors` for `data.blog.authors`.
+    pub fn insert(&mut self, name: &str, value: Value) {
+        let mut parts: Vec<_> = name.split('/').collect();
+        let last = parts.pop().unwrap_or_default();
+        let object = parts.into_iter().fold(&mut self.root, |object, part| {
+            let entry = object
+                .entry(part)
+                .or_insert_with(|| Value::Object(Map::new()));
+            if !entry.is_object() {
+                *entry = Value::Object(Map::new());
+            }
+            entry.as_object_mut().expect("entry should be an object")
+        });
+        object.insert(last.to_owned(), value);
+    }
+
+    /// The value at a path like `data.site.author`, or `item.title` for the variable of an
+    /// enclosing `sr-for`.
+    fn get<'a>(&'a self, path: &str, scope: &[(String, &'a Value)]) -> Option<&'a Value> {
+        let mut parts = path.trim().split('.');
+        let first = parts.next()?;
+        let value = match scope.iter().rev().find(|(var, _)| var == first) {
+            Some((_, value)) => *value,
+            None if first == Self::ROOT => self.root.get(parts.next()?)?,
+            None => return None,
+        };
+
+        parts.try_fold(value, |value, part| match value {
+            Value::Object(map) => map.get(part),
+            Value::Array(items) => items.get(part.parse::<usize>().ok()?),
+            _ => None,
+        })
+    }
+
+    /// Whether `path` names a value in data rather than a property.
+    fn is_data_path(path: &str, scope: &[(String, &Value)]) -> bool {
+        let first = path.trim().split('.').next().unwrap_or_default();
+        first == Self::ROOT || scope.iter().any(|(var, _)| var == first)
+    }
+
+    /// The text of the value at `path`, if it is a string, number or boolean.
+    fn text(&self, path: &str, scope: &[(String, &Value)]) -> Result<String, TemplateError> {
+        match self.get(path, scope) {
+            Some(Value::String(text)) => Ok(text.clone()),
+            Some(value @ (Value::Number(_) | Value::Bool(_))) => Ok(value.to_string()),
+            Some(Value::Null) => Ok(String::new()),
+            _ => Err(TemplateError::MissingData(path.to_owned())),
+        }
+    }
+
+    /// Repeats every element with `sr-for` in `events` for each value in its array, and replaces
+    /// the content of elements with `sr-text` and the attributes bound with `sr-attr-*` by the text
+    /// of their values.
+    pub fn expand(&self, events: Vec<XmlEvent>) -> Result<Vec<XmlEvent>, TemplateError> {
+        self.expand_with(events, &[], true)
+    }
+
+    /// Like [`Data::expand`], but elements with `sr-for` are left as they are, for the template to
+    /// compile into loops.
+    pub fn expand_outside_loops(
+        &self,
+        events: Vec<XmlEvent>,
+    ) -> Result<Vec<XmlEvent>, TemplateError> {
+        self.expand_with(events, &[], false)
+    }
+
+    /// Splits the value of an `sr-for` attribute, like `item in data.nav`, into the variable and
+    /// the path of the array.
+    pub fn parse_loop(value: &str) -> Result<(String, String), TemplateError> {
+        value
+            .split_once(" in ")
+            .map(|(var, path)| (var.trim().to_owned(), path.trim().to_owned()))
+            .filter(|(var, _)| !var.is_empty() && !var.contains('.'))
+            .ok_or_else(|| TemplateError::MalformedAttribute(Self::FOR.to_owned()))
+    }
+
+    /// Repeats `element` (without its `sr-for` attribute) for each value in the array at `path`,
+    /// named by `var` inside it.
+    pub fn repeat(
+        &self,
+        var: &str,
+        path: &str,
+        element: &[XmlEvent],
+    ) -> Result<Vec<XmlEvent>, TemplateError> {
+        self.repeat_with(var, path, element, &[])
+    }
+
+    fn repeat_with<'a>(
+        &'a self,
+        var: &str,
+        path: &str,
+        element: &[XmlEvent],
+        scope: &[(String, &'a Value)],
+    ) -> Result<Vec<XmlEvent>, TemplateError> {
+        let items = self
+            .get(path, scope)
+            .and_then(Value::as_array)
+            .ok_or_else(|| TemplateError::MissingData(path.to_owned()))?;
+
+        let mut repeated = Vec::new();
+        for item in items {
+            let scope = [scope, &[(var.to_owned(), item)]].concat();
+            repeated.append(&mut self.expand_with(element.to_vec(), &scope, true)?);
+        }
+        Ok(repeated)
+    }
+
+    fn expand_with<'a>(
+        &'a self,
+        events: Vec<XmlEvent>,
+        scope: &[(String, &'a Value)],
+        loops: bool,
+    ) -> Result<Vec<XmlEvent>, TemplateError> {
+        let mut expanded = Vec::new();
+        let mut events = events.into_iter();
+
+        while let Some(event) = events.next() {
+            match event {
+                XmlEvent::StartElement {
+                    name,
+                    mut attributes,
+                    namespace,
+                } if attributes.iter().any(|a| {
+                    let local_name = &a.name.local_name;
+                    local_name == Self::FOR
+                        || local_name == Self::TEXT
+                        || (local_name.starts_with(Self::ATTR)
+                            && Self::is_data_path(&a.value, scope))
+                }) =>
+                {
+                    if let Some(index) = attributes
+                        .iter()
+                        .position(|a| a.name.local_name == Self::FOR)
+                    {
+                        // Outside loops, the element is kept whole, with its `sr-for`.
+                        let value = loops.then(|| attributes.remove(index).value);
+                        let mut element = vec![XmlEvent::StartElement {
+                            name: name.clone(),
+                            attributes,
+                            namespace,
+                        }];
+                        element.extend(take_children(&mut events));
+                        element.push(XmlEvent::EndElement { name });
+
+                        match value {
+                            Some(value) => {
+                                let (var, path) = Self::parse_loop(&value)?;
+                                expanded
+                                    .append(&mut self.repeat_with(&var, &path, &element, scope)?);
+                            }
+                            None => expanded.append(&mut element),
+                        }
+                        continue;
+                    }
+
+                    let mut text = None;
+                    let mut kept = Vec::new();
+                    for attribute in attributes {
+                        let local_name = &attribute.name.local_name;
+                        if local_name == Self::TEXT {
+                            text = Some(escape_text(&self.text(&attribute.value, scope)?));
+                        } else if let Some(bound) = local_name
+                            .strip_prefix(Self::ATTR)
+                            .filter(|_| Self::is_data_path(&attribute.value, scope))
+                        {
+                            let value = self.text(&attribute.value, scope)?;
+                            kept.push(OwnedAttribute::new(
+                                OwnedName::local(bound),
+                                escape_attribute(&value),
+                            ));
+                        } else {
+                            kept.push(attribute);
+                        }
+                    }
+
+                    expanded.push(XmlEvent::StartElement {
+                        name: name.clone(),
+                        attributes: kept,
+                        namespace,
+                    });
+                    if let Some(text) = text {
+                        take_children(&mut events);
+                        expanded.push(XmlEvent::Characters(text));
+                        expanded.push(XmlEvent::EndElement { name });
+                    }
+                }
+                event => expanded.push(event),
+            }
+        }
+
+        Ok(expanded)
+    }
+}
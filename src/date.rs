@@ -0,0 +1,128 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A date and time in UTC, as written in page properties like `date`.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Date {
+    year: i64,
+    month: u32,
+    day: u32,
+    seconds: u32,
+}
+
+impl Date {
+    /// Parses `YYYY-MM-DD`, optionally followed by a time (`THH:MM` or `THH:MM:SS`). Time zones
+    /// are ignored.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let (date, time) = match text.find(['T', ' ']) {
+            Some(i) => (&text[..i], Some(&text[i + 1..])),
+            None => (text, None),
+        };
+
+        let mut parts = date.splitn(3, '-');
+        let year = parts.next()?.parse().ok()?;
+        let month = parts
+            .next()?
+            .parse()
+            .ok()
+            .filter(|m| (1..=12).contains(m))?;
+        let day = parts
+            .next()?
+            .parse()
+            .ok()
+            .filter(|d| (1..=31).contains(d))?;
+
+        let mut seconds = 0;
+        if let Some(time) = time {
+            let time = time.split(['Z', '+', '-']).next().unwrap_or_default();
+            for (part, scale) in time.split(':').zip([3600, 60, 1]) {
+                let part = part.split('.').next().unwrap_or_default();
+                seconds += part.parse::<u32>().ok()? * scale;
+            }
+        }
+
+        Some(Self {
+            year,
+            month,
+            day,
+            seconds,
+        })
+    }
+
+    pub fn from_system_time(time: SystemTime) -> Self {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+        Self {
+            year,
+            month,
+            day,
+            seconds: secs.rem_euclid(86400) as u32,
+        }
+    }
+
+    fn days(&self) -> i64 {
+        let year = if self.month <= 2 {
+            self.year - 1
+        } else {
+            self.year
+        };
+        let era = year.div_euclid(400);
+        let yoe = year - era * 400;
+        let mp = (self.month as i64 + 9) % 12;
+        let doy = (153 * mp + 2) / 5 + self.day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146097 + doe - 719468
+    }
+
+    fn time(&self) -> String {
+        format!(
+            "{:02}:{:02}:{:02}",
+            self.seconds / 3600,
+            self.seconds / 60 % 60,
+            self.seconds % 60
+        )
+    }
+
+    /// `2024-01-31`, as used in sitemaps.
+    pub fn to_w3c_date(self) -> String {
+        format!("{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+
+    /// `2024-01-31T12:00:00Z`, as used in Atom feeds.
+    pub fn to_rfc3339(self) -> String {
+        format!("{}T{}Z", self.to_w3c_date(), self.time())
+    }
+
+    /// `Wed, 31 Jan 2024 12:00:00 +0000`, as used in RSS feeds.
+    pub fn to_rfc2822(self) -> String {
+        format!(
+            "{}, {:02} {} {:04} {} +0000",
+            WEEKDAYS[self.days().rem_euclid(7) as usize],
+            self.day,
+            MONTHS[self.month as usize - 1],
+            self.year,
+            self.time()
+        )
+    }
+}
+
+// From http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
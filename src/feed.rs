@@ -0,0 +1,164 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    time::SystemTime,
+};
+
+use xml::{
+    reader::XmlEvent,
+    writer::{self, XmlEvent as WriteEvent},
+    EmitterConfig, EventWriter,
+};
+
+use crate::{config::FeedConfig, date::Date, xml::prop_text};
+
+pub const ATOM_FILE: &str = "feed.xml";
+pub const RSS_FILE: &str = "rss.xml";
+
+struct Entry {
+    title: String,
+    url: String,
+    date: Option<Date>,
+    summary: Option<String>,
+}
+
+/// Writes the configured feeds for the pages of a collection into `out_path`.
+pub fn write_feeds(
+    config: &FeedConfig,
+    base_url: &str,
+    items: &[HashMap<String, Vec<XmlEvent>>],
+    out_path: &Path,
+) -> Result<(), writer::Error> {
+    let base_url = base_url.trim_end_matches('/');
+    let prop = |props: &HashMap<String, Vec<XmlEvent>>, name: &str| {
+        prop_text(props, name).map(|text| text.trim().to_owned())
+    };
+
+    let mut entries: Vec<_> = items
+        .iter()
+        .map(|props| Entry {
+            title: prop(props, &config.title_prop).unwrap_or_default(),
+            url: base_url.to_owned() + &prop(props, "__url").unwrap_or_default(),
+            date: prop(props, &config.date_prop).and_then(|date| Date::parse(&date)),
+            summary: prop(props, &config.summary_prop),
+        })
+        .collect();
+    entries.sort_by_key(|entry| Reverse(entry.date));
+    entries.truncate(config.limit);
+
+    if config.atom {
+        let mut out = BufWriter::new(File::create(out_path.join(ATOM_FILE))?);
+        write_atom(config, base_url, &entries, &mut out)?;
+        out.flush()?;
+    }
+    if config.rss {
+        let mut out = BufWriter::new(File::create(out_path.join(RSS_FILE))?);
+        write_rss(config, base_url, &entries, &mut out)?;
+        out.flush()?;
+    }
+
+    Ok(())
+}
+
+fn writer_config() -> EmitterConfig {
+    EmitterConfig {
+        perform_indent: true,
+        ..Default::default()
+    }
+}
+
+fn write_element<W: Write>(
+    writer: &mut EventWriter<W>,
+    name: &str,
+    text: &str,
+) -> Result<(), writer::Error> {
+    writer.write(WriteEvent::start_element(name))?;
+    writer.write(WriteEvent::characters(text))?;
+    writer.write(WriteEvent::end_element())
+}
+
+fn write_atom(
+    config: &FeedConfig,
+    base_url: &str,
+    entries: &[Entry],
+    out: impl Write,
+) -> Result<(), writer::Error> {
+    let mut writer = EventWriter::new_with_config(out, writer_config());
+    let home = format!("{base_url}/");
+    let updated = entries
+        .iter()
+        .filter_map(|entry| entry.date)
+        .max()
+        .unwrap_or_else(|| Date::from_system_time(SystemTime::now()))
+        .to_rfc3339();
+
+    writer.write(WriteEvent::start_element("feed").default_ns("http://www.w3.org/2005/Atom"))?;
+    write_element(&mut writer, "title", &config.title)?;
+    if !config.description.is_empty() {
+        write_element(&mut writer, "subtitle", &config.description)?;
+    }
+    writer.write(
+        WriteEvent::start_element("link")
+            .attr("href", &format!("{base_url}/{ATOM_FILE}"))
+            .attr("rel", "self"),
+    )?;
+    writer.write(WriteEvent::end_element())?;
+    writer.write(WriteEvent::start_element("link").attr("href", &home))?;
+    writer.write(WriteEvent::end_element())?;
+    write_element(&mut writer, "id", &home)?;
+    write_element(&mut writer, "updated", &updated)?;
+
+    for entry in entries {
+        writer.write(WriteEvent::start_element("entry"))?;
+        write_element(&mut writer, "title", &entry.title)?;
+        writer.write(WriteEvent::start_element("link").attr("href", &entry.url))?;
+        writer.write(WriteEvent::end_element())?;
+        write_element(&mut writer, "id", &entry.url)?;
+        write_element(
+            &mut writer,
+            "updated",
+            &entry.date.map(Date::to_rfc3339).unwrap_or(updated.clone()),
+        )?;
+        if let Some(summary) = &entry.summary {
+            write_element(&mut writer, "summary", summary)?;
+        }
+        writer.write(WriteEvent::end_element())?;
+    }
+
+    writer.write(WriteEvent::end_element())
+}
+
+fn write_rss(
+    config: &FeedConfig,
+    base_url: &str,
+    entries: &[Entry],
+    out: impl Write,
+) -> Result<(), writer::Error> {
+    let mut writer = EventWriter::new_with_config(out, writer_config());
+
+    writer.write(WriteEvent::start_element("rss").attr("version", "2.0"))?;
+    writer.write(WriteEvent::start_element("channel"))?;
+    write_element(&mut writer, "title", &config.title)?;
+    write_element(&mut writer, "link", &format!("{base_url}/"))?;
+    write_element(&mut writer, "description", &config.description)?;
+
+    for entry in entries {
+        writer.write(WriteEvent::start_element("item"))?;
+        write_element(&mut writer, "title", &entry.title)?;
+        write_element(&mut writer, "link", &entry.url)?;
+        write_element(&mut writer, "guid", &entry.url)?;
+        if let Some(date) = entry.date {
+            write_element(&mut writer, "pubDate", &date.to_rfc2822())?;
+        }
+        if let Some(summary) = &entry.summary {
+            write_element(&mut writer, "description", summary)?;
+        }
+        writer.write(WriteEvent::end_element())?;
+    }
+
+    writer.write(WriteEvent::end_element())?;
+    writer.write(WriteEvent::end_element())
+}
@@ -0,0 +1,121 @@
+use std::path::Path;
+
+use syntect::{
+    highlighting::{Theme, ThemeSet},
+    html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator},
+    parsing::SyntaxSet,
+    util::LinesWithEndings,
+};
+use xml::reader::XmlEvent;
+
+use crate::{config::HighlightConfig, xml::take_children};
+
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// Highlights `<pre><code class="language-*">` blocks at build time.
+pub struct Highlighter {
+    syntaxes: SyntaxSet,
+    theme: Theme,
+}
+
+impl Highlighter {
+    pub fn new(config: &HighlightConfig) -> Result<Self, String> {
+        let theme = if config.theme.ends_with(".tmTheme") {
+            ThemeSet::get_theme(Path::new(&config.theme))
+                .map_err(|err| format!("Failed to load theme {}: {err}", config.theme))?
+        } else {
+            ThemeSet::load_defaults()
+                .themes
+                .remove(&config.theme)
+                .ok_or_else(|| format!("Unknown highlighting theme '{}'.", config.theme))?
+        };
+
+        Ok(Self {
+            syntaxes: SyntaxSet::load_defaults_newlines(),
+            theme,
+        })
+    }
+
+    /// The stylesheet for the classes used in highlighted code.
+    pub fn css(&self) -> String {
+        css_for_theme_with_class_style(&self.theme, CLASS_STYLE)
+            .expect("theme should convert to css")
+    }
+
+    /// Highlights `code` as HTML, or returns `None` if the language isn't known.
+    pub fn highlight_code(&self, language: &str, code: &str) -> Option<String> {
+        let syntax = self.syntaxes.find_syntax_by_token(language)?;
+        let mut generator =
+            ClassedHTMLGenerator::new_with_class_style(syntax, &self.syntaxes, CLASS_STYLE);
+        for line in LinesWithEndings::from(code) {
+            generator
+                .parse_html_for_line_which_includes_newline(line)
+                .ok()?;
+        }
+
+        Some(generator.finalize())
+    }
+
+    /// Highlights every code block in `events` that only contains text. The highlighted code is
+    /// kept as HTML in a text event.
+    pub fn highlight(&self, events: Vec<XmlEvent>) -> Vec<XmlEvent> {
+        let mut highlighted = Vec::new();
+        let mut events = events.into_iter();
+        let mut in_pre = false;
+
+        while let Some(event) = events.next() {
+            match event {
+                XmlEvent::StartElement {
+                    name,
+                    attributes,
+                    namespace,
+                } if in_pre && name.local_name == "code" => {
+                    let language = attributes
+                        .iter()
+                        .find(|a| a.name.local_name == "class")
+                        .and_then(|a| {
+                            a.value
+                                .split_whitespace()
+                                .find_map(|class| class.strip_prefix("language-"))
+                        })
+                        .map(str::to_owned);
+                    let children = take_children(&mut events);
+                    let code = children
+                        .iter()
+                        .map(|event| match event {
+                            XmlEvent::Characters(text)
+                            | XmlEvent::CData(text)
+                            | XmlEvent::Whitespace(text) => Some(text.as_str()),
+                            _ => None,
+                        })
+                        .collect::<Option<String>>();
+
+                    highlighted.push(XmlEvent::StartElement {
+                        name: name.clone(),
+                        attributes,
+                        namespace,
+                    });
+                    match language
+                        .zip(code)
+                        .and_then(|(language, code)| self.highlight_code(&language, &code))
+                    {
+                        Some(html) => highlighted.push(XmlEvent::Characters(html)),
+                        None => highlighted.extend(children),
+                    }
+                    highlighted.push(XmlEvent::EndElement { name });
+                }
+                XmlEvent::StartElement { ref name, .. } => {
+                    in_pre = name.local_name == "pre";
+                    highlighted.push(event);
+                }
+                XmlEvent::Whitespace(_) => highlighted.push(event),
+                event => {
+                    in_pre = false;
+                    highlighted.push(event);
+                }
+            }
+        }
+
+        highlighted
+    }
+}
@@ -0,0 +1,28 @@
+//! A static site generator for simple router, used by the `simple-router` binary.
+//!
+//! Read a [`Config`] with [`Config::load`] (or build one in memory), then build the site with
+//! [`build::build`], which returns a [`build::BuildReport`] instead of printing errors. Progress is
+//! sent to a [`build::Logger`], and [`plugin::Plugin`]s can change pages and static files.
+//! [`server::start`] runs the dev server, and [`build::load_template`] loads the template to
+//! render pages with [`Template`].
+
+mod assets;
+pub mod build;
+mod collection;
+mod component;
+pub mod config;
+mod data;
+mod date;
+mod feed;
+mod highlight;
+mod links;
+mod manifest;
+mod markdown;
+mod minify;
+pub mod plugin;
+pub mod server;
+mod sitemap;
+pub mod xml;
+
+pub use config::Config;
+pub use xml::Template;
@@ -0,0 +1,309 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{build::list_files, config::Config, manifest::Manifest};
+
+/// Attributes holding links, checked for each element.
+const LINKS: [(&str, &str); 4] = [
+    ("a", "href"),
+    ("img", "src"),
+    ("link", "href"),
+    ("script", "src"),
+];
+
+/// A link to a file that doesn't exist in the output directory.
+pub struct BrokenLink {
+    /// The file the link was written in: the page, or the template, layout or component it came
+    /// from. If that isn't known, the rendered file.
+    pub file: PathBuf,
+    /// The line the link was written on, if it was found in `file`.
+    pub line: Option<usize>,
+    pub element: String,
+    pub url: String,
+}
+
+impl Display for BrokenLink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.file.to_string_lossy())?;
+        if let Some(line) = self.line {
+            write!(f, ":{line}")?;
+        }
+        write!(f, ": broken link to '{}' in <{}>", self.url, self.element)
+    }
+}
+
+/// Checks the links in every page in the output directory. `manifest` is used to find the source
+/// of each page, to report broken links where they were written.
+pub fn check_links(config: &Config, manifest: &Manifest) -> io::Result<Vec<BrokenLink>> {
+    let out_path = Path::new(&config.out.path);
+    if !out_path.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No site built at {}", config.out.path),
+        ));
+    }
+    let files: HashSet<String> = list_files(out_path)?
+        .iter()
+        .map(|file| url_path(out_path, file))
+        .collect();
+
+    // The template, layouts and components can contain links too.
+    let mut shared_sources = vec![PathBuf::from(&config.source.template)];
+    shared_sources.append(&mut list_files(Path::new(&config.source.layouts_path))?);
+    shared_sources.append(&mut list_files(Path::new(&config.source.components_path))?);
+    let mut written = HashMap::new();
+
+    let mut broken = Vec::new();
+    for file in list_files(out_path)? {
+        if file.extension().is_none_or(|ext| ext != "html") {
+            continue;
+        }
+        let html = fs::read_to_string(&file)?;
+        let page_url = url_path(out_path, &file);
+
+        for (_, element, url) in links(&html) {
+            let Some(target) = resolve(&page_url, &url, config.base_url.as_deref()) else {
+                continue;
+            };
+            if exists(&files, &target, element == "a") {
+                continue;
+            }
+
+            // The line in the rendered file isn't reported, since it isn't one the author wrote
+            // (and it's always 1 if the page is minified).
+            let page = manifest.source(&file).map(Path::to_path_buf);
+            let location = page.iter().chain(&shared_sources).find_map(|source| {
+                let line = written
+                    .entry(source.clone())
+                    .or_insert_with(|| written_links(source))
+                    .iter()
+                    .find(|(_, e, u)| *e == element && *u == url)?
+                    .0;
+                Some((source.clone(), Some(line)))
+            });
+            let (file, line) = location.unwrap_or_else(|| (page.unwrap_or(file.clone()), None));
+            // A link in the template or a layout is reported once, not for every page.
+            if line.is_some()
+                && broken
+                    .iter()
+                    .any(|b: &BrokenLink| b.file == file && b.line == line && b.url == url)
+            {
+                continue;
+            }
+
+            broken.push(BrokenLink {
+                file,
+                line,
+                element: element.to_owned(),
+                url,
+            });
+        }
+    }
+
+    Ok(broken)
+}
+
+/// The links written as HTML in a source file, with the line of each. Links in Markdown syntax
+/// aren't found.
+fn written_links(source: &Path) -> Vec<(usize, &'static str, String)> {
+    let Ok(text) = fs::read_to_string(source) else {
+        return Vec::new();
+    };
+    links(&text)
+        .into_iter()
+        .map(|(offset, element, url)| (text[..offset].matches('\n').count() + 1, element, url))
+        .collect()
+}
+
+/// The URL path of a file in the output directory, like `/blog/index.html`.
+fn url_path(out_path: &Path, file: &Path) -> String {
+    let relative = file.strip_prefix(out_path).unwrap_or(file);
+    String::from("/") + &relative.to_string_lossy().replace('\\', "/")
+}
+
+/// Finds the links in `html`, returning the offset of each element, its name, and the
+/// (unescaped) URL.
+fn links(html: &str) -> Vec<(usize, &'static str, String)> {
+    let mut links = Vec::new();
+    let mut offset = 0;
+
+    while let Some(start) = html[offset..].find('<') {
+        let start = offset + start;
+        let rest = &html[start + 1..];
+        if let Some(comment) = rest.strip_prefix("!--") {
+            offset = comment
+                .find("-->")
+                .map_or(html.len(), |end| start + 4 + end + 3);
+            continue;
+        }
+
+        let name_end = rest
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        let (attributes, end) = attributes(&rest[name_end..]);
+        offset = start + 1 + name_end + end;
+
+        if let Some((element, attribute)) = LINKS.iter().find(|(element, _)| *element == name) {
+            if let Some((_, value)) = attributes.iter().find(|(name, _)| name == attribute) {
+                links.push((start, *element, unescape(value)));
+            }
+        }
+
+        // Skip the content of scripts and styles, which may contain `<`.
+        if name == "script" || name == "style" {
+            let closing = format!("</{name}");
+            offset = html[offset..]
+                .to_ascii_lowercase()
+                .find(&closing)
+                .map_or(html.len(), |end| offset + end);
+        }
+    }
+
+    links
+}
+
+/// Parses the attributes of a tag, up to its `>`. Returns them, and the length of the tag.
+fn attributes(tag: &str) -> (Vec<(String, &str)>, usize) {
+    let mut attributes = Vec::new();
+    let mut rest = tag;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+        if rest.is_empty() || rest.starts_with('>') {
+            break;
+        }
+
+        let name_end = rest
+            .find(|c: char| c.is_ascii_whitespace() || c == '=' || c == '>' || c == '/')
+            .unwrap_or(rest.len())
+            .max(1);
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let value = match rest.strip_prefix('=') {
+            Some(value) => {
+                let value = value.trim_start();
+                match value.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let end = value[1..].find(quote).map_or(value.len(), |end| end + 1);
+                        rest = value.get(end + 1..).unwrap_or_default();
+                        &value[1..end]
+                    }
+                    _ => {
+                        let end = value
+                            .find(|c: char| c.is_ascii_whitespace() || c == '>')
+                            .unwrap_or(value.len());
+                        rest = &value[end..];
+                        &value[..end]
+                    }
+                }
+            }
+            None => "",
+        };
+        attributes.push((name, value));
+    }
+
+    let end = tag.len() - rest.len() + usize::from(rest.starts_with('>'));
+    (attributes, end)
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Resolves `url`, linked from the page at `page_url`, to a path in the output directory.
+/// Returns `None` for links to other sites, or that only change the fragment.
+fn resolve(page_url: &str, url: &str, base_url: Option<&str>) -> Option<String> {
+    let mut url = url.trim();
+    if let Some(local) = base_url.and_then(|base| url.strip_prefix(base.trim_end_matches('/'))) {
+        url = local;
+        if url.is_empty() {
+            url = "/";
+        }
+    }
+
+    let scheme_end = url.find(':');
+    let path_start = url.find(['/', '?', '#']);
+    if url.starts_with("//")
+        || scheme_end.is_some_and(|colon| path_start.is_none_or(|start| colon < start))
+    {
+        return None;
+    }
+    let url = &url[..url.find(['?', '#']).unwrap_or(url.len())];
+    if url.is_empty() {
+        return None;
+    }
+
+    let joined = if url.starts_with('/') {
+        url.to_owned()
+    } else {
+        let dir = &page_url[..page_url.rfind('/').map_or(0, |end| end + 1)];
+        format!("{dir}{url}")
+    };
+
+    let mut segments = Vec::new();
+    for segment in joined.split('/') {
+        match segment {
+            "" | "." => (),
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(percent_decode(segment)),
+        }
+    }
+    let mut path = String::from("/") + &segments.join("/");
+    if joined.ends_with('/') && path != "/" {
+        path.push('/');
+    }
+    Some(path)
+}
+
+fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = (bytes[i] == b'%')
+            .then(|| segment.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Whether `path` can be loaded: like the dev server, it's a file, or a directory with an
+/// `index.html`. Links (`is_page`) are followed by the router, which loads the page's
+/// `.page.json` instead.
+fn exists(files: &HashSet<String>, path: &str, is_page: bool) -> bool {
+    let path = path.trim_end_matches('/');
+    if files.contains(path) || files.contains(&format!("{path}/index.html")) {
+        return true;
+    }
+
+    is_page
+        && match path.strip_suffix(".html") {
+            Some(page) => files.contains(&format!("{page}.page.json")),
+            None => files.contains(&format!("{path}/index.page.json")),
+        }
+}
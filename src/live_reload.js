@@ -0,0 +1,23 @@
+(() => {
+  const events = new EventSource("{EVENTS_PATH}");
+
+  events.addEventListener("reload", () => location.reload());
+
+  events.addEventListener("pages", (e) => {
+    /** @type {String[]} */
+    const pages = JSON.parse(e.data);
+    const pathOf = (dataURL) => new URL(dataURL, location.origin).pathname;
+
+    for (const dataURL of Object.keys(router.pageCache)) {
+      if (pages.includes(pathOf(dataURL))) delete router.pageCache[dataURL];
+    }
+
+    const current = history.state?.dataURL;
+    if (current != null && pages.includes(pathOf(current))) {
+      router._load(current).then((loaded) => {
+        if (loaded) window.dispatchEvent(new CustomEvent("navigate"));
+        else location.reload();
+      });
+    }
+  });
+})();
@@ -1,24 +1,46 @@
-use std::{fs, time::Instant};
+use std::{process, time::Instant};
 
-use build::Verbosity;
-use clap::{crate_name, crate_version, value_parser, Arg, Command};
-use config::Config;
-
-mod build;
-mod config;
-mod server;
-mod xml;
+use clap::{crate_name, crate_version, value_parser, Arg, ArgAction, Command};
+use simple_router::{
+    build::{self, BuildReport, Logger, Verbosity},
+    server, Config,
+};
 
 fn main() {
+    let verbosity = Arg::new("verbosity")
+        .long("verbosity")
+        .short('v')
+        .value_parser(["silent", "low", "high"])
+        .default_value("high");
     let mut cmd = Command::new(crate_name!())
         .subcommand(
-            Command::new("build").about("Build static site.").arg(
-                Arg::new("verbosity")
-                    .long("verbosity")
-                    .short('v')
-                    .value_parser(["silent", "low", "high"])
-                    .default_value("high"),
-            ),
+            Command::new("build")
+                .about("Build static site.")
+                .arg(verbosity.clone())
+                .arg(
+                    Arg::new("jobs")
+                        .long("jobs")
+                        .short('j')
+                        .value_parser(value_parser!(usize))
+                        .help("Number of threads to build with. Defaults to one per CPU."),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(["text", "json"])
+                        .default_value("text")
+                        .help("Print a JSON report instead of the build log."),
+                ),
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Check the site for errors, without building it.")
+                .arg(
+                    Arg::new("links")
+                        .long("links")
+                        .action(ArgAction::SetTrue)
+                        .help("Also check links in the built site."),
+                ),
         )
         .subcommand(
             Command::new("dev")
@@ -29,73 +51,101 @@ fn main() {
                         .value_parser(value_parser!(u16))
                         .default_value("3000"),
                 )
-                .arg(Arg::new("host").long("host").default_value("localhost")),
+                .arg(Arg::new("host").long("host").default_value("localhost"))
+                .arg(verbosity.default_value("low")),
         )
         .version(crate_version!());
     let matches = cmd.get_matches_mut();
 
-    let config = match get_config() {
+    let json = matches
+        .subcommand_matches("build")
+        .is_some_and(|subcmd| subcmd.get_one::<String>("format").unwrap() == "json");
+    let config = match Config::load("./simple-router.toml") {
         Ok(c) => c,
         Err(msg) => {
-            println!("\x1b[31mError reading config\x1b[0m: {msg}");
-            return;
+            let report = BuildReport::config_error(msg);
+            if json {
+                println!("{:#}", report.to_json());
+            } else {
+                println!("\x1b[31mError reading config\x1b[0m: {}", report.errors[0]);
+            }
+            process::exit(report.exit_code());
         }
     };
 
     match matches.subcommand() {
         Some(("build", subcmd)) => {
-            let verbosity: Verbosity = subcmd
-                .get_one::<String>("verbosity")
-                .unwrap()
-                .as_str()
-                .try_into()
-                .expect("Verbosity level must be silent, low, or high.");
+            let verbosity: Verbosity = if json {
+                Verbosity::Silent
+            } else {
+                subcmd
+                    .get_one::<String>("verbosity")
+                    .unwrap()
+                    .as_str()
+                    .try_into()
+                    .expect("Verbosity level must be silent, low, or high.")
+            };
+
+            let jobs = subcmd
+                .get_one::<usize>("jobs")
+                .map_or_else(build::default_jobs, |jobs| (*jobs).max(1));
 
             if verbosity >= Verbosity::Low {
                 println!("\x1b[35mBuilding static site...\x1b[0m");
             }
             let time_start = Instant::now();
 
-            let result = build::build(verbosity, config);
+            let report = build::build(&config, jobs, &verbosity, &[]);
 
-            if let Err(err) = result {
-                println!("\n\x1b[31mErrors while building static site\x1b[0m: {err}.");
-            } else if verbosity >= Verbosity::Low {
-                println!(
-                    "\x1b[32mWebsite built in {:.2}s.\x1b[0m",
-                    time_start.elapsed().as_secs_f64()
-                )
+            if json {
+                println!("{:#}", report.to_json());
+            } else if !report.is_ok() {
+                Verbosity::High.info("\n\x1b[31mErrors while building static site\x1b[0m:");
+                report.print();
+            } else {
+                report.print();
+                if verbosity >= Verbosity::Low {
+                    println!(
+                        "\x1b[32mWebsite built in {:.2}s.\x1b[0m",
+                        time_start.elapsed().as_secs_f64()
+                    )
+                }
+            }
+            process::exit(report.exit_code());
+        }
+        Some(("check", subcmd)) => {
+            let links = subcmd.get_flag("links");
+            let report = build::check(&config, links);
+            report.print();
+            if !report.is_ok() {
+                process::exit(report.exit_code());
+            }
+            println!(
+                "\x1b[32mChecked {} pages.\x1b[0m",
+                report.summary.pages.len()
+            );
+            if links {
+                println!("\x1b[32mNo broken links.\x1b[0m");
             }
         }
         Some(("dev", subcmd)) => {
-            server::start(
+            let verbosity: Verbosity = subcmd
+                .get_one::<String>("verbosity")
+                .unwrap()
+                .as_str()
+                .try_into()
+                .expect("Verbosity level must be silent, low, or high.");
+            if let Err(err) = server::start(
                 *subcmd.get_one("port").unwrap(),
                 subcmd.get_one::<String>("host").unwrap().clone(),
                 config,
-            );
+                &verbosity,
+            ) {
+                println!("\x1b[31mError starting server\x1b[0m: {err}");
+                process::exit(1);
+            }
         }
         None => cmd.print_help().unwrap(),
         _ => (),
     }
 }
-
-fn get_config() -> Result<Config, String> {
-    let file = fs::read_to_string("simple-router.toml")
-        .map_err(|_| String::from("No file found at ./simple-router.toml."))?;
-    let config: Config = toml::from_str(&file).map_err(|e| e.message().to_owned())?;
-
-    let major_version = crate_version!()
-        .split(".")
-        .take(2)
-        .collect::<Vec<_>>()
-        .join(".");
-    if config.library_version != major_version {
-        Err(format!(
-            "Incorrect config version. Using version {crate}, but config is on {conf}.",
-            crate = crate_version!(),
-            conf = config.library_version
-        ))
-    } else {
-        Ok(config)
-    }
-}
@@ -0,0 +1,215 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    hash::{Hash, Hasher},
+    io::{self, BufReader, BufWriter},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::{xxh3_64, Xxh3Default};
+
+const MANIFEST_FILE: &str = ".simple-router-manifest.json";
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct FileRecord {
+    source: PathBuf,
+    modified: u64,
+    len: u64,
+    hash: u64,
+}
+
+impl FileRecord {
+    /// The hash of the source's content, or of the generated file's for
+    /// [`Manifest::check_contents`].
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    fn stat(source: &Path) -> io::Result<(u64, u64)> {
+        let metadata = fs::metadata(source)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Ok((modified, metadata.len()))
+    }
+}
+
+/// What the sitemap needs to know about a written page, and whether it lists a collection.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PageRecord {
+    pub lastmod: String,
+    pub noindex: bool,
+    #[serde(default)]
+    pub lists_collection: bool,
+}
+
+/// Records which source produced each output file, so that unchanged files can be skipped on the
+/// next build.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Manifest {
+    pub config: u64,
+    pub template: u64,
+    #[serde(default)]
+    pub collections: u64,
+    files: BTreeMap<PathBuf, FileRecord>,
+    #[serde(default)]
+    pages: BTreeMap<PathBuf, PageRecord>,
+}
+
+impl Manifest {
+    pub fn new(config: u64, template: u64) -> Self {
+        Self {
+            config,
+            template,
+            collections: 0,
+            files: BTreeMap::new(),
+            pages: BTreeMap::new(),
+        }
+    }
+
+    fn path(out_path: &str) -> PathBuf {
+        Path::new(out_path).join(MANIFEST_FILE)
+    }
+
+    pub fn load(out_path: &str) -> Option<Self> {
+        let file = File::open(Self::path(out_path)).ok()?;
+        serde_json::from_reader(BufReader::new(file)).ok()
+    }
+
+    pub fn save(&self, out_path: &str) -> io::Result<()> {
+        let file = BufWriter::new(File::create(Self::path(out_path))?);
+        serde_json::to_writer(file, self).map_err(io::Error::other)
+    }
+
+    /// Removes the manifest from disk, so that an interrupted build is followed by a full one.
+    pub fn invalidate(out_path: &str) -> io::Result<()> {
+        match fs::remove_file(Self::path(out_path)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    /// Compares `source` against the record for `out`. Returns whether `out` is up to date, and
+    /// the record to store in the next manifest.
+    pub fn check(&self, source: &Path, out: &Path) -> io::Result<(bool, FileRecord)> {
+        let (modified, len) = FileRecord::stat(source)?;
+        let previous = self
+            .files
+            .get(out)
+            .filter(|record| record.source == source && out.exists());
+
+        if let Some(previous) = previous {
+            if previous.modified == modified && previous.len == len {
+                return Ok((true, previous.clone()));
+            }
+        }
+
+        let record = FileRecord {
+            source: source.to_path_buf(),
+            modified,
+            len,
+            hash: hash_file(source)?,
+        };
+        Ok((previous.is_some_and(|p| p.hash == record.hash), record))
+    }
+
+    /// Like [`Manifest::check`], for a file generated from `source`, like a bundle: `out` is up
+    /// to date if it was generated with the same `contents`.
+    pub fn check_contents(
+        &self,
+        source: &Path,
+        out: &Path,
+        contents: &[u8],
+    ) -> io::Result<(bool, FileRecord)> {
+        let (modified, len) = FileRecord::stat(source)?;
+        let record = FileRecord {
+            source: source.to_path_buf(),
+            modified,
+            len,
+            hash: hash_bytes(contents),
+        };
+        let fresh = self.files.get(out).is_some_and(|previous| {
+            previous.source == source && previous.hash == record.hash && out.exists()
+        });
+        Ok((fresh, record))
+    }
+
+    /// The source of the output file `out`.
+    pub fn source(&self, out: &Path) -> Option<&Path> {
+        self.files.get(out).map(|record| record.source.as_path())
+    }
+
+    pub fn insert(&mut self, out: PathBuf, record: FileRecord) {
+        self.files.insert(out, record);
+    }
+
+    pub fn insert_page(&mut self, out: PathBuf, record: PageRecord) {
+        self.pages.insert(out, record);
+    }
+
+    pub fn page(&self, out: &Path) -> Option<&PageRecord> {
+        self.pages.get(out)
+    }
+
+    /// Every page written by this build, including unchanged ones.
+    pub fn pages(&self) -> impl Iterator<Item = (&PathBuf, &PageRecord)> {
+        self.pages.iter()
+    }
+
+    /// Keeps every output file that `previous` recorded for `source`.
+    pub fn retain_from(&mut self, previous: &Manifest, source: &Path) {
+        for (out, record) in &previous.files {
+            if record.source == source {
+                self.files.insert(out.clone(), record.clone());
+                if let Some(page) = previous.pages.get(out) {
+                    self.pages.insert(out.clone(), page.clone());
+                }
+            }
+        }
+    }
+
+    /// Deletes every output file recorded in `previous` that is no longer produced by this build.
+    pub fn remove_stale(&self, previous: &Manifest, out_path: &str) -> io::Result<usize> {
+        let mut removed = 0;
+        for out in previous.files.keys() {
+            if self.files.contains_key(out) {
+                continue;
+            }
+
+            match fs::remove_file(out) {
+                Ok(()) => removed += 1,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+                Err(err) => return Err(err),
+            }
+
+            let mut dir = out.parent();
+            while let Some(parent) = dir {
+                if parent == Path::new(out_path) || fs::remove_dir(parent).is_err() {
+                    break;
+                }
+                dir = parent.parent();
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+// The hashes are written to the manifest and used in fingerprinted names, so they use xxh3 rather
+// than `DefaultHasher`, which may change between Rust releases.
+pub fn hash_value(value: &impl Hash) -> u64 {
+    let mut hasher = Xxh3Default::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn hash_file(path: &Path) -> io::Result<u64> {
+    Ok(hash_bytes(&fs::read(path)?))
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    xxh3_64(bytes)
+}
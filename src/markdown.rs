@@ -0,0 +1,308 @@
+use std::collections::{HashMap, HashSet};
+
+use ::xml::{reader::XmlEvent, ParserConfig};
+use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd};
+
+use crate::{
+    highlight::Highlighter,
+    xml::{self, TemplateError},
+};
+
+/// Parses a Markdown page. Front matter (YAML between `---`, or TOML between `+++`) becomes
+/// text properties, and the rendered body becomes the `content` property, which front matter
+/// can't set.
+pub fn parse_page(
+    source: &str,
+    parser_config: ParserConfig,
+    highlighter: Option<&Highlighter>,
+) -> Result<HashMap<String, Vec<XmlEvent>>, TemplateError> {
+    let (front_matter, body) = split_front_matter(source);
+
+    let content = format!("<content>{}</content>", render(body, highlighter));
+    let mut props = xml::parse_page(content.as_bytes(), parser_config)?;
+
+    // Text stays escaped, like it is in the rendered HTML.
+    for events in props.values_mut() {
+        for event in events {
+            if let XmlEvent::Characters(text) = event {
+                *text = xml::escape_text(text);
+            }
+        }
+    }
+
+    if let Some(front_matter) = front_matter {
+        for (prop, value) in front_matter? {
+            if prop == "content" {
+                return Err(TemplateError::FrontMatter(String::from(
+                    "`content` is reserved for the rendered body.",
+                )));
+            }
+            props.insert(prop, vec![XmlEvent::Characters(xml::escape_text(&value))]);
+        }
+    }
+    props.insert(String::from(xml::MARKDOWN_PROP), Vec::new());
+
+    Ok(props)
+}
+
+type FrontMatter = Result<Vec<(String, String)>, TemplateError>;
+
+fn split_front_matter(source: &str) -> (Option<FrontMatter>, &str) {
+    for (delimiter, parse) in [
+        ("---", parse_yaml as fn(&str) -> FrontMatter),
+        ("+++", parse_toml),
+    ] {
+        let Some(rest) = source.strip_prefix(delimiter) else {
+            continue;
+        };
+        let Some(rest) = rest.strip_prefix('\n').or(rest.strip_prefix("\r\n")) else {
+            continue;
+        };
+
+        let end = rest
+            .match_indices(delimiter)
+            .find(|(i, _)| *i == 0 || rest[..*i].ends_with('\n'));
+        if let Some((i, _)) = end {
+            let body = &rest[i + delimiter.len()..];
+            return (Some(parse(&rest[..i])), body);
+        }
+    }
+
+    (None, source)
+}
+
+fn parse_yaml(front_matter: &str) -> FrontMatter {
+    let map: serde_yaml::Mapping = serde_yaml::from_str(front_matter)
+        .map_err(|err| TemplateError::FrontMatter(err.to_string()))?;
+
+    Ok(map
+        .into_iter()
+        .filter_map(|(key, value)| Some((key.as_str()?.to_owned(), yaml_text(value)?)))
+        .collect())
+}
+
+fn yaml_text(value: serde_yaml::Value) -> Option<String> {
+    Some(match value {
+        serde_yaml::Value::String(s) => s,
+        serde_yaml::Value::Number(n) => n.to_string(),
+        serde_yaml::Value::Bool(b) => b.to_string(),
+        serde_yaml::Value::Null => String::new(),
+        // Lists, like tags, are joined with commas.
+        serde_yaml::Value::Sequence(items)
+            if items.iter().all(|i| !i.is_sequence() && !i.is_mapping()) =>
+        {
+            items
+                .into_iter()
+                .map(yaml_text)
+                .collect::<Option<Vec<_>>>()?
+                .join(", ")
+        }
+        value => serde_yaml::to_string(&value).ok()?.trim().to_owned(),
+    })
+}
+
+fn parse_toml(front_matter: &str) -> FrontMatter {
+    let table: toml::Table = toml::from_str(front_matter)
+        .map_err(|err| TemplateError::FrontMatter(err.message().to_owned()))?;
+
+    Ok(table
+        .into_iter()
+        .map(|(key, value)| (key, toml_text(value)))
+        .collect())
+}
+
+fn toml_text(value: toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s,
+        toml::Value::Array(items) if items.iter().all(|i| !i.is_array() && !i.is_table()) => items
+            .into_iter()
+            .map(toml_text)
+            .collect::<Vec<_>>()
+            .join(", "),
+        value => value.to_string(),
+    }
+}
+
+fn render(markdown: &str, highlighter: Option<&Highlighter>) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_HEADING_ATTRIBUTES;
+    let mut events: Vec<_> = Parser::new_ext(markdown, options).collect();
+
+    // Give every heading without an explicit id an anchor based on its text.
+    let mut ids: HashSet<String> = events
+        .iter()
+        .filter_map(|e| match e {
+            Event::Start(Tag::Heading { id: Some(id), .. }) => Some(id.to_string()),
+            _ => None,
+        })
+        .collect();
+    for i in 0..events.len() {
+        let Event::Start(Tag::Heading { id: None, .. }) = &events[i] else {
+            continue;
+        };
+
+        let text: String = events[i + 1..]
+            .iter()
+            .take_while(|e| !matches!(e, Event::End(TagEnd::Heading(_))))
+            .filter_map(|e| match e {
+                Event::Text(text) | Event::Code(text) => Some(text.as_ref()),
+                _ => None,
+            })
+            .collect();
+        let slug = slugify(&text);
+
+        let mut id = slug.clone();
+        let mut n = 1;
+        while !ids.insert(id.clone()) {
+            id = format!("{slug}-{n}");
+            n += 1;
+        }
+
+        if let Event::Start(Tag::Heading { id: heading_id, .. }) = &mut events[i] {
+            *heading_id = Some(CowStr::from(id));
+        }
+    }
+
+    if let Some(highlighter) = highlighter {
+        events = highlight_code_blocks(events, highlighter);
+    }
+    let events = close_void_elements(events);
+
+    let mut out = String::new();
+    html::push_html(&mut out, events.into_iter());
+    out
+}
+
+fn highlight_code_blocks<'a>(events: Vec<Event<'a>>, highlighter: &Highlighter) -> Vec<Event<'a>> {
+    let mut highlighted = Vec::new();
+    let mut events = events.into_iter();
+
+    while let Some(event) = events.next() {
+        let Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) = &event else {
+            highlighted.push(event);
+            continue;
+        };
+        let language = info
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .next()
+            .unwrap_or_default()
+            .to_owned();
+
+        let mut block = vec![event];
+        let mut code = String::new();
+        for event in events.by_ref() {
+            if let Event::Text(text) = &event {
+                code.push_str(text);
+            }
+            let end = matches!(event, Event::End(TagEnd::CodeBlock));
+            block.push(event);
+            if end {
+                break;
+            }
+        }
+
+        match highlighter.highlight_code(&language, &code) {
+            Some(html) => highlighted.push(Event::Html(CowStr::from(format!(
+                "<pre><code class=\"language-{language}\">{html}</code></pre>\n"
+            )))),
+            None => highlighted.append(&mut block),
+        }
+    }
+
+    highlighted
+}
+
+/// Closes void elements in raw HTML, like `<br>`, so the rendered page can be parsed as XML. A block
+/// of HTML comes as one event per line, so they're joined first.
+fn close_void_elements(events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    let mut closed = Vec::new();
+    let mut events = events.into_iter().peekable();
+
+    while let Some(event) = events.next() {
+        match event {
+            Event::Html(html) => {
+                let mut html = html.into_string();
+                while let Some(Event::Html(next)) = events.peek() {
+                    html.push_str(next);
+                    events.next();
+                }
+                closed.push(Event::Html(CowStr::from(close_void_tags(&html))));
+            }
+            Event::InlineHtml(html) => {
+                closed.push(Event::InlineHtml(CowStr::from(close_void_tags(&html))));
+            }
+            event => closed.push(event),
+        }
+    }
+
+    closed
+}
+
+fn close_void_tags(html: &str) -> String {
+    let mut closed = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        closed.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let name_len = rest[1..]
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(rest.len() - 1);
+        let name = rest[1..1 + name_len].to_ascii_lowercase();
+        if !xml::is_void(&name) {
+            closed.push('<');
+            rest = &rest[1..];
+            continue;
+        }
+
+        // Find the end of the tag, skipping `>` in quoted attribute values.
+        let mut quote = None;
+        let end = rest.char_indices().find(|&(_, c)| match quote {
+            Some(q) if c == q => {
+                quote = None;
+                false
+            }
+            Some(_) => false,
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                false
+            }
+            None => c == '>',
+        });
+        let Some((end, _)) = end else {
+            break;
+        };
+
+        let tag = rest[..end].trim_end();
+        closed.push_str(tag);
+        if !tag.ends_with('/') {
+            closed.push_str(" /");
+        }
+        closed.push('>');
+        rest = &rest[end + 1..];
+    }
+
+    closed.push_str(rest);
+    closed
+}
+
+fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.trim().chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() || c == '_' || c == '-' {
+            slug.push(c);
+        } else if c.is_whitespace() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    if slug.is_empty() {
+        String::from("section")
+    } else {
+        slug
+    }
+}
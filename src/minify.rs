@@ -0,0 +1,312 @@
+/// Elements around which whitespace isn't rendered.
+const BLOCK: [&str; 52] = [
+    "!doctype",
+    "address",
+    "article",
+    "aside",
+    "base",
+    "blockquote",
+    "body",
+    "caption",
+    "col",
+    "colgroup",
+    "dd",
+    "details",
+    "dialog",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hr",
+    "html",
+    "li",
+    "link",
+    "main",
+    "meta",
+    "nav",
+    "noscript",
+    "ol",
+    "option",
+    "p",
+    "pre",
+    "script",
+    "section",
+    "style",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+];
+/// Elements whose content is kept as-is, or minified as CSS or JavaScript.
+const RAW: [&str; 4] = ["pre", "textarea", "script", "style"];
+
+enum Token<'a> {
+    Tag { text: &'a str, name: String },
+    Text(&'a str),
+    Raw(String),
+}
+
+/// Whether the content of the element `name` is kept as-is (or minified as CSS or JavaScript) rather
+/// than collapsed.
+pub fn is_raw(name: &str) -> bool {
+    RAW.iter().any(|raw| raw.eq_ignore_ascii_case(name))
+}
+
+impl Token<'_> {
+    fn is_block(&self) -> bool {
+        match self {
+            Self::Tag { name, .. } => BLOCK.contains(&name.as_str()),
+            _ => false,
+        }
+    }
+}
+
+/// Removes comments and collapses whitespace in HTML, keeping a single space where whitespace is
+/// rendered (between inline elements and text). The content of `<pre>` and `<textarea>` is kept,
+/// and inline `<style>` and `<script>` are minified.
+pub fn minify_html(html: &str) -> String {
+    let tokens = tokenize(html);
+    let mut minified = String::with_capacity(html.len());
+
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Tag { text, .. } => minified.push_str(text),
+            Token::Raw(text) => minified.push_str(text),
+            Token::Text(text) => {
+                let mut collapsed = String::with_capacity(text.len());
+                for c in text.chars() {
+                    if !c.is_ascii_whitespace() {
+                        collapsed.push(c);
+                    } else if !collapsed.ends_with(' ') {
+                        collapsed.push(' ');
+                    }
+                }
+
+                let mut text = collapsed.as_str();
+                if i == 0 || tokens[i - 1].is_block() {
+                    text = text.trim_start();
+                }
+                if tokens.get(i + 1).is_none_or(Token::is_block) {
+                    text = text.trim_end();
+                }
+                minified.push_str(text);
+            }
+        }
+    }
+
+    minified
+}
+
+fn tokenize(html: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+
+        let starts_tag = rest.starts_with('<')
+            && rest[1..]
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '/' || c == '!');
+        if !starts_tag {
+            let end = rest
+                .char_indices()
+                .skip(1)
+                .find(|&(_, c)| c == '<')
+                .map_or(rest.len(), |(end, _)| end);
+            tokens.push(Token::Text(&rest[..end]));
+            rest = &rest[end..];
+            continue;
+        }
+
+        // Find the end of the tag, skipping over quoted attribute values.
+        let mut quote = None;
+        let end = rest
+            .char_indices()
+            .find(|&(_, c)| match quote {
+                Some(q) if c == q => {
+                    quote = None;
+                    false
+                }
+                Some(_) => false,
+                None if c == '"' || c == '\'' => {
+                    quote = Some(c);
+                    false
+                }
+                None => c == '>',
+            })
+            .map_or(rest.len(), |(end, _)| end + 1);
+        let text = &rest[..end];
+        rest = &rest[end..];
+
+        let name = text[1..]
+            .trim_start_matches('/')
+            .split(|c: char| c.is_ascii_whitespace() || c == '/' || c == '>')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let opens_raw =
+            !text.starts_with("</") && !text.ends_with("/>") && RAW.contains(&name.as_str());
+        tokens.push(Token::Tag {
+            text,
+            name: name.clone(),
+        });
+
+        if opens_raw {
+            let end = rest
+                .to_ascii_lowercase()
+                .find(&format!("</{name}"))
+                .unwrap_or(rest.len());
+            let content = &rest[..end];
+            tokens.push(Token::Raw(match name.as_str() {
+                "style" => minify_css(content),
+                "script" => minify_js(content),
+                _ => content.to_owned(),
+            }));
+            rest = &rest[end..];
+        }
+    }
+
+    tokens
+}
+
+/// Removes comments and unneeded whitespace from CSS. Strings are kept as-is.
+pub fn minify_css(css: &str) -> String {
+    let mut minified = String::with_capacity(css.len());
+    let mut chars = css.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                minified.push(c);
+                while let Some(inner) = chars.next() {
+                    minified.push(inner);
+                    if inner == '\\' {
+                        minified.extend(chars.next());
+                    } else if inner == c {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                for inner in chars.by_ref() {
+                    if previous == '*' && inner == '/' {
+                        break;
+                    }
+                    previous = inner;
+                }
+            }
+            c if c.is_ascii_whitespace() => {
+                while chars.peek().is_some_and(char::is_ascii_whitespace) {
+                    chars.next();
+                }
+                let next = chars.peek().copied().unwrap_or('{');
+                let previous = minified.chars().last().unwrap_or('{');
+                if !"{};,>:".contains(previous) && !"{};,>".contains(next) {
+                    minified.push(' ');
+                }
+            }
+            '}' if minified.ends_with(';') => {
+                minified.pop();
+                minified.push(c);
+            }
+            c => minified.push(c),
+        }
+    }
+
+    minified
+}
+
+/// Removes indentation and empty lines from JavaScript. This is conservative: scripts with
+/// template literals, which may span several lines, are only trimmed, and lines continuing a string
+/// after a `\` keep their indentation.
+pub fn minify_js(js: &str) -> String {
+    if js.contains('`') {
+        return js.trim().to_owned();
+    }
+
+    let mut lines = Vec::new();
+    let mut continued = false;
+    for line in js.lines() {
+        let line = if continued {
+            line.trim_end()
+        } else {
+            line.trim()
+        };
+        if line.is_empty() && !continued {
+            continue;
+        }
+        // An odd number of backslashes escapes the line break.
+        continued = (line.len() - line.trim_end_matches('\\').len()) % 2 == 1;
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_pre_and_textarea() {
+        assert_eq!(
+            minify_html("<div>\n  <pre>  a\n    b </pre>\n  <textarea> x\n  y</textarea>\n</div>"),
+            "<div><pre>  a\n    b </pre><textarea> x\n  y</textarea></div>"
+        );
+    }
+
+    #[test]
+    fn collapses_whitespace_between_inline_elements() {
+        assert_eq!(
+            minify_html("<p>\n  Some   <b>bold</b>\n  text\n</p>"),
+            "<p>Some <b>bold</b> text</p>"
+        );
+    }
+
+    #[test]
+    fn keeps_escaped_quotes_in_css_strings() {
+        assert_eq!(
+            minify_css("a::before {\n  content: \"say \\\"hi  {there}\\\"\" ;\n}"),
+            "a::before{content:\"say \\\"hi  {there}\\\"\"}"
+        );
+        assert_eq!(
+            minify_css("a { content: '\\'' ; }  b { color: red; }"),
+            "a{content:'\\''}b{color:red}"
+        );
+    }
+
+    #[test]
+    fn keeps_line_continuations_in_js() {
+        assert_eq!(
+            minify_js("  const a = \"one \\\n    two\";\n\n  call(a);\n"),
+            "const a = \"one \\\n    two\";\ncall(a);"
+        );
+        // An escaped backslash doesn't continue the line.
+        assert_eq!(
+            minify_js("  const a = \"\\\\\";\n    call(a);"),
+            "const a = \"\\\\\";\ncall(a);"
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsStr,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+};
+
+use ::xml::{reader::XmlEvent, ParserConfig};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    build::{BuildError, BuildReport},
+    config::PluginConfig,
+    xml::{self, TemplateError},
+};
+
+/// The points in a build where plugins are called.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Hook {
+    PageParsed,
+    BeforeRender,
+    AfterRender,
+    StaticFile,
+    BuildFinished,
+}
+
+/// A page and its properties, each written as XML.
+#[derive(Serialize)]
+pub struct PageProps {
+    /// The source file, like `pages/index.html`.
+    pub source: PathBuf,
+    /// The output file, like `out/index.html`.
+    pub out: PathBuf,
+    pub props: BTreeMap<String, String>,
+}
+
+/// A rendered page, before it's written.
+#[derive(Serialize)]
+pub struct RenderedPage {
+    pub source: PathBuf,
+    pub out: PathBuf,
+    pub html: String,
+    /// The `.page.json` file, loaded by the router when navigating to the page.
+    pub json: String,
+}
+
+/// A static file, or bundle, before it's written.
+pub struct StaticFile {
+    pub source: PathBuf,
+    pub out: PathBuf,
+    pub contents: Vec<u8>,
+}
+
+/// Changes pages and static files as the site is built. Every hook does nothing by default, and
+/// returns an error message to fail the page, or the build.
+pub trait Plugin: Sync {
+    /// The name used in errors. Pages and static files are built again when the names of the
+    /// plugins change.
+    fn name(&self) -> &str;
+
+    /// Whether the plugin does anything for `hook`, to skip preparing its input otherwise.
+    fn handles(&self, _hook: Hook) -> bool {
+        true
+    }
+
+    /// Called with the properties of a page after it's parsed, before it's added to collections.
+    fn page_parsed(&self, _page: &mut PageProps) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called with the properties of a page before it's rendered with its layout, once for each
+    /// page of a paginated listing.
+    fn before_render(&self, _page: &mut PageProps) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called with a rendered page before it's written.
+    fn after_render(&self, _page: &mut RenderedPage) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called with each static file and bundle that is written to the output directory.
+    fn static_file(&self, _file: &mut StaticFile) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called once the build is done, whether or not it failed.
+    fn build_finished(&self, _report: &BuildReport) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// What an external plugin prints, with only the fields it changed.
+#[derive(Deserialize)]
+struct Output {
+    props: Option<BTreeMap<String, String>>,
+    html: Option<String>,
+    json: Option<String>,
+    contents: Option<String>,
+}
+
+impl PluginConfig {
+    /// Runs the command with `input` and the hook as JSON on stdin. Returns what it printed, or
+    /// `None` if it printed nothing.
+    fn run(&self, hook: Hook, input: Value) -> Result<Option<Output>, String> {
+        let mut input = input;
+        input["hook"] = json!(hook);
+        let input = input.to_string();
+
+        let mut child = Command::new("sh")
+            .args(["-c", &self.command])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| err.to_string())?;
+        // Written from another thread, in case the command prints before reading everything.
+        let mut stdin = child.stdin.take().unwrap();
+        let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+        let output = child.wait_with_output().map_err(|err| err.to_string())?;
+        // The command may not read its input if it has nothing to change.
+        let _ = writer.join();
+
+        if !output.status.success() {
+            return Err(format!("exited with {}", output.status));
+        }
+        if output.stdout.trim_ascii().is_empty() {
+            return Ok(None);
+        }
+        serde_json::from_slice(&output.stdout)
+            .map(Some)
+            .map_err(|err| format!("invalid output: {err}"))
+    }
+
+    fn runs(&self, hook: Hook) -> bool {
+        self.hooks.contains(&hook)
+    }
+
+    fn change_props(&self, hook: Hook, page: &mut PageProps) -> Result<(), String> {
+        if !self.runs(hook) {
+            return Ok(());
+        }
+        let input = serde_json::to_value(&*page).map_err(|err| err.to_string())?;
+        if let Some(props) = self.run(hook, input)?.and_then(|o| o.props) {
+            page.props = props;
+        }
+        Ok(())
+    }
+}
+
+impl Plugin for PluginConfig {
+    fn name(&self) -> &str {
+        &self.command
+    }
+
+    fn handles(&self, hook: Hook) -> bool {
+        self.runs(hook)
+    }
+
+    fn page_parsed(&self, page: &mut PageProps) -> Result<(), String> {
+        self.change_props(Hook::PageParsed, page)
+    }
+
+    fn before_render(&self, page: &mut PageProps) -> Result<(), String> {
+        self.change_props(Hook::BeforeRender, page)
+    }
+
+    fn after_render(&self, page: &mut RenderedPage) -> Result<(), String> {
+        if !self.runs(Hook::AfterRender) {
+            return Ok(());
+        }
+        let input = serde_json::to_value(&*page).map_err(|err| err.to_string())?;
+        if let Some(output) = self.run(Hook::AfterRender, input)? {
+            page.html = output.html.unwrap_or(std::mem::take(&mut page.html));
+            page.json = output.json.unwrap_or(std::mem::take(&mut page.json));
+        }
+        Ok(())
+    }
+
+    /// Only text files are sent to external plugins, limited to `extensions` if there are any.
+    fn static_file(&self, file: &mut StaticFile) -> Result<(), String> {
+        let extension = file.out.extension().and_then(OsStr::to_str);
+        if !self.runs(Hook::StaticFile)
+            || (!self.extensions.is_empty()
+                && extension.is_none_or(|ext| !self.extensions.iter().any(|e| e == ext)))
+        {
+            return Ok(());
+        }
+        let Ok(contents) = std::str::from_utf8(&file.contents) else {
+            return Ok(());
+        };
+
+        let input = json!({ "source": file.source, "out": file.out, "contents": contents });
+        if let Some(contents) = self.run(Hook::StaticFile, input)?.and_then(|o| o.contents) {
+            file.contents = contents.into_bytes();
+        }
+        Ok(())
+    }
+
+    fn build_finished(&self, report: &BuildReport) -> Result<(), String> {
+        if self.runs(Hook::BuildFinished) {
+            self.run(Hook::BuildFinished, json!({ "report": report.to_json() }))?;
+        }
+        Ok(())
+    }
+}
+
+/// The error for a plugin that failed.
+pub(crate) fn plugin_error(plugin: &dyn Plugin, msg: String, source: Option<&Path>) -> BuildError {
+    BuildError::Other {
+        msg: format!("Plugin '{}' failed: {msg}", plugin.name()),
+        source: source.map(|source| source.to_string_lossy().to_string()),
+    }
+}
+
+/// Calls `hook` (either [`Hook::PageParsed`] or [`Hook::BeforeRender`]) of every plugin handling
+/// it with the properties of a page, written as XML, and parses them back.
+pub(crate) fn change_props(
+    plugins: &[&dyn Plugin],
+    hook: Hook,
+    source: &Path,
+    out: &Path,
+    props: HashMap<String, Vec<XmlEvent>>,
+    parser_config: ParserConfig,
+) -> Result<HashMap<String, Vec<XmlEvent>>, BuildError> {
+    if !handled(plugins, hook) {
+        return Ok(props);
+    }
+    let with_source = |err: TemplateError| {
+        BuildError::from(err).with_source(source.to_string_lossy().to_string())
+    };
+
+    let mut page = PageProps {
+        source: source.to_path_buf(),
+        out: out.to_path_buf(),
+        props: props
+            .iter()
+            .map(|(prop, events)| Ok((prop.clone(), xml::write_events(events)?)))
+            .collect::<Result<_, TemplateError>>()
+            .map_err(with_source)?,
+    };
+    for plugin in plugins.iter().filter(|plugin| plugin.handles(hook)) {
+        let result = match hook {
+            Hook::PageParsed => plugin.page_parsed(&mut page),
+            Hook::BeforeRender => plugin.before_render(&mut page),
+            _ => unreachable!("only page hooks change props"),
+        };
+        result.map_err(|msg| plugin_error(*plugin, msg, Some(source)))?;
+    }
+
+    let mut parsed = HashMap::new();
+    for (prop, value) in page.props {
+        let mut props = xml::parse_page(
+            format!("<{prop}>{value}</{prop}>").as_bytes(),
+            parser_config.clone(),
+        )
+        .map_err(with_source)?;
+        parsed.insert(prop.clone(), props.remove(&prop).unwrap_or_default());
+    }
+    Ok(parsed)
+}
+
+/// Calls every plugin with a static file, returning its new contents.
+pub(crate) fn change_static_file(
+    plugins: &[&dyn Plugin],
+    source: &Path,
+    out: &Path,
+    contents: Vec<u8>,
+) -> Result<Vec<u8>, BuildError> {
+    let mut file = StaticFile {
+        source: source.to_path_buf(),
+        out: out.to_path_buf(),
+        contents,
+    };
+    for plugin in plugins
+        .iter()
+        .filter(|plugin| plugin.handles(Hook::StaticFile))
+    {
+        plugin
+            .static_file(&mut file)
+            .map_err(|msg| plugin_error(*plugin, msg, Some(source)))?;
+    }
+    Ok(file.contents)
+}
+
+/// Whether any of `plugins` handles `hook`.
+pub(crate) fn handled(plugins: &[&dyn Plugin], hook: Hook) -> bool {
+    plugins.iter().any(|plugin| plugin.handles(hook))
+}
@@ -1,10 +1,10 @@
 use std::{
     ffi::OsStr,
     fs,
-    io::{BufRead, BufReader, Write},
+    io::{self, BufRead, BufReader, Write},
     net::{TcpListener, TcpStream},
     path::{Path, PathBuf},
-    sync::mpsc,
+    sync::{mpsc, Arc, Mutex},
     thread,
     time::{Duration, Instant},
 };
@@ -13,35 +13,35 @@ use mime_guess::MimeGuess;
 use notify::{RecursiveMode, Watcher};
 
 use crate::{
-    build::{self, Verbosity},
+    build::{self, BuildSummary, Logger},
     config::Config,
 };
 
+const EVENTS_PATH: &str = "/__sr/events";
+
+type Clients = Arc<Mutex<Vec<TcpStream>>>;
+
 fn join(mut a: PathBuf, b: impl AsRef<Path>) -> PathBuf {
     a.push(b);
     a
 }
 
-pub fn start(port: u16, hostname: String, config: Config) {
-    println!("\x1b[35m[BUILD]\x1b[0m Buildng website...");
-    let time_start = Instant::now();
-
-    if let Err(err) = build::build(Verbosity::Low, config.clone()) {
-        println!("\x1b[31m[BUILD FAILED]\x1b[31m {err}")
-    } else {
-        println!(
-            "\x1b[35m[BUILD]\x1b[0m Website built in {:.2}s.",
-            time_start.elapsed().as_secs_f32()
-        );
-    }
+/// Builds the site, then serves the output directory at `hostname:port`, rebuilding it and
+/// reloading open pages when a source file changes. Only returns if the server can't start.
+pub fn start(port: u16, hostname: String, config: Config, log: &dyn Logger) -> io::Result<()> {
+    log.status("\x1b[35m[BUILD]\x1b[0m Building website...");
+    build_site(&config, log);
 
-    println!("\x1b[36m[SERVER]\x1b[0m Starting web server at {hostname}:{port}...");
+    log.status(&format!(
+        "\x1b[36m[SERVER]\x1b[0m Starting web server at {hostname}:{port}..."
+    ));
+    let listener = TcpListener::bind((hostname, port))?;
 
     let directory: PathBuf = config.out.path.clone().into();
     let not_found: PathBuf = config.js.not_found.clone().into();
-    let server_handle = thread::spawn(move || listen(port, hostname, &directory, &not_found));
+    let clients = Clients::default();
 
-    let current_dir = fs::canonicalize(String::from(".")).expect("failed to get directory");
+    let current_dir = fs::canonicalize(".")?;
     let mut excludes = Vec::new();
     excludes.push(join(current_dir.clone(), Path::new(&config.out.path)));
     for path in &config.source.exclude {
@@ -49,55 +49,85 @@ pub fn start(port: u16, hostname: String, config: Config) {
     }
 
     let (tx, rx) = mpsc::channel();
-    let pages_path = fs::canonicalize(&config.source.pages_path).expect("Failed to get directory");
-    let static_path =
-        fs::canonicalize(&config.source.static_path).expect("Failed to get directory");
+    let watched: Vec<PathBuf> = [
+        &config.source.pages_path,
+        &config.source.static_path,
+        &config.source.template,
+        &config.source.layouts_path,
+        &config.source.components_path,
+        &config.source.data_path,
+    ]
+    .into_iter()
+    .map(|path| fs::canonicalize(path).unwrap_or_else(|_| join(current_dir.clone(), path)))
+    .collect();
 
-    let mut watcher = notify::recommended_watcher(tx).expect("Failed to listen for events");
+    let mut watcher = notify::recommended_watcher(tx).map_err(io::Error::other)?;
     watcher
         .watch(Path::new("."), RecursiveMode::Recursive)
-        .expect("Failed to start watcher");
-
-    let mut last_build: Option<Instant> = None;
-    thread::spawn(move || {
-        for res in rx {
-            match res {
-                Ok(event) => {
-                    if !event.kind.is_access()
-                        && last_build.is_none_or(|d| d.elapsed() >= Duration::from_secs(1))
-                        && event.paths.iter().any(|ev| {
-                            !excludes.iter().any(|p| ev.starts_with(p))
-                                && (ev.starts_with(&pages_path) || ev.starts_with(&static_path))
-                        })
-                    {
-                        handle_file_update(config.clone());
-                        last_build = Some(Instant::now());
+        .map_err(io::Error::other)?;
+
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut last_build: Option<Instant> = None;
+            for res in rx {
+                match res {
+                    Ok(event) => {
+                        if !event.kind.is_access()
+                            && last_build.is_none_or(|d| d.elapsed() >= Duration::from_secs(1))
+                            && event.paths.iter().any(|ev| {
+                                !excludes.iter().any(|p| ev.starts_with(p))
+                                    && watched.iter().any(|p| ev.starts_with(p))
+                            })
+                        {
+                            log.status("\x1b[35m[BUILD]\x1b[0m Changes detected, building...");
+                            if let Some(summary) = build_site(&config, log) {
+                                notify_clients(&clients, &summary);
+                            }
+                            last_build = Some(Instant::now());
+                        }
                     }
+                    Err(error) => log.error(&format!(
+                        "\x1b[35m[BUILD]\x1b[0m Error watching files: {error}"
+                    )),
                 }
-                Err(error) => println!("\x1b[35m[BUILD]\x1b[0m Error watching files: {error}"),
             }
-        }
-    });
+        });
 
-    server_handle.join().expect("Failed to start server");
+        listen(&listener, &directory, &not_found, &clients, log);
+    });
+    Ok(())
 }
 
-fn listen(port: u16, hostname: String, directory: &Path, not_found: &Path) {
-    let listener = TcpListener::bind((hostname, port)).unwrap();
-
+/// Serves requests until the listener stops. Failed connections are logged and dropped.
+fn listen(
+    listener: &TcpListener,
+    directory: &Path,
+    not_found: &Path,
+    clients: &Clients,
+    log: &dyn Logger,
+) {
     for stream in listener.incoming() {
-        let mut stream = stream.unwrap();
-        let response = handle_connection(&mut stream, directory, not_found);
-
-        stream.write_all(&response).unwrap();
+        let result = stream.and_then(|mut stream| {
+            let response = handle_connection(&mut stream, directory, not_found, clients, log);
+            stream.write_all(&response)
+        });
+        if let Err(err) = result {
+            log.error(&format!("\x1b[31m[SERVER]\x1b[0m Connection failed: {err}"));
+        }
     }
 }
 
-fn handle_connection(stream: &mut TcpStream, directory: &Path, not_found: &Path) -> Vec<u8> {
-    let buf_reader = BufReader::new(stream);
+fn handle_connection(
+    stream: &mut TcpStream,
+    directory: &Path,
+    not_found: &Path,
+    clients: &Clients,
+    log: &dyn Logger,
+) -> Vec<u8> {
+    let buf_reader = BufReader::new(&mut *stream);
     let request: Vec<_> = buf_reader
         .lines()
-        .map(|result| result.unwrap())
+        .map_while(Result::ok)
         .take_while(|line| !line.is_empty())
         .collect();
     if request.is_empty() {
@@ -110,6 +140,13 @@ fn handle_connection(stream: &mut TcpStream, directory: &Path, not_found: &Path)
     };
 
     match *method {
+        "GET" if *path == EVENTS_PATH => {
+            if let Ok(client) = stream.try_clone() {
+                clients.lock().unwrap().push(client);
+            }
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n"
+                .to_vec()
+        }
         "GET" => {
             let path_buf = Path::new(path.strip_prefix("/").unwrap_or(path));
             let mut file = directory.join(path_buf);
@@ -119,10 +156,10 @@ fn handle_connection(stream: &mut TcpStream, directory: &Path, not_found: &Path)
             }
 
             let status = if !file.exists() {
-                println!(
+                log.status(&format!(
                     "\x1b[31m[404]\x1b[0m Not found: ./{}",
                     file.to_string_lossy()
-                );
+                ));
                 file = directory.join(not_found);
                 if !file.exists() {
                     return format!("HTTP/1.1 404 NOT FOUND\r\n\r\nCannot {method} {path}",)
@@ -134,7 +171,10 @@ fn handle_connection(stream: &mut TcpStream, directory: &Path, not_found: &Path)
             } else if file.extension() == Some(OsStr::new("html"))
                 || file.extension() == Some(OsStr::new("json"))
             {
-                println!("\x1b[32m[GET]\x1b[0m ./{}", file.to_string_lossy());
+                log.status(&format!(
+                    "\x1b[32m[GET]\x1b[0m ./{}",
+                    file.to_string_lossy()
+                ));
                 "HTTP/1.1 200 OK"
             } else {
                 "HTTP/1.1 200 OK"
@@ -143,7 +183,13 @@ fn handle_connection(stream: &mut TcpStream, directory: &Path, not_found: &Path)
             let mime_type = MimeGuess::from_path(&file)
                 .first()
                 .map_or(String::new(), |mime| mime.essence_str().to_owned());
-            let mut contents = fs::read(file).unwrap();
+            let is_html = file.extension() == Some(OsStr::new("html"));
+            let Ok(mut contents) = fs::read(&file) else {
+                return b"HTTP/1.1 500 INTERNAL SERVER ERROR\r\n\r\n".to_vec();
+            };
+            if is_html {
+                contents = inject_live_reload(contents);
+            }
             let length = contents.len();
 
             let mut response = format!(
@@ -155,7 +201,7 @@ fn handle_connection(stream: &mut TcpStream, directory: &Path, not_found: &Path)
             response
         }
         _ => {
-            println!("\x1b[31m[{method}]\x1b[0m {path}");
+            log.status(&format!("\x1b[31m[{method}]\x1b[0m {path}"));
             format!("HTTP/1.1 404 NOT FOUND\r\n\r\nCannot {method} {path}")
                 .as_bytes()
                 .to_vec()
@@ -163,17 +209,51 @@ fn handle_connection(stream: &mut TcpStream, directory: &Path, not_found: &Path)
     }
 }
 
-fn handle_file_update(config: Config) {
-    println!("\x1b[35m[BUILD]\x1b[0m Changes detected, building...");
+fn inject_live_reload(mut html: Vec<u8>) -> Vec<u8> {
+    let script = format!(
+        "<script>{}</script>",
+        include_str!("live_reload.js").replace("{EVENTS_PATH}", EVENTS_PATH)
+    );
+    let position = html
+        .windows(7)
+        .position(|w| w == b"</head>")
+        .unwrap_or(html.len());
+    html.splice(position..position, script.into_bytes());
+    html
+}
+
+fn notify_clients(clients: &Clients, summary: &BuildSummary) {
+    let message = if summary.copied == 0 && summary.removed == 0 && !summary.template_changed {
+        format!(
+            "event: pages\ndata: {}\n\n",
+            serde_json::to_string(&summary.pages).unwrap()
+        )
+    } else {
+        String::from("event: reload\ndata: \n\n")
+    };
+
+    clients
+        .lock()
+        .unwrap()
+        .retain_mut(|client| client.write_all(message.as_bytes()).is_ok());
+}
+
+/// Builds the site and logs the result, returning what changed if it succeeded.
+fn build_site(config: &Config, log: &dyn Logger) -> Option<BuildSummary> {
     let time_start = Instant::now();
 
-    let result = build::build(Verbosity::Low, config.clone());
+    let report = build::build(config, build::default_jobs(), log, &[]);
 
-    match result {
-        Ok(_) => println!(
+    if report.is_ok() {
+        report.log(log);
+        log.status(&format!(
             "\x1b[35m[BUILD]\x1b[0m Website built in {:.2}s.",
             time_start.elapsed().as_secs_f32()
-        ),
-        Err(err) => println!("\x1b[31m[BUILD FAILED]\x1b[31m {err}"),
+        ));
+        Some(report.summary)
+    } else {
+        log.error("\x1b[31m[BUILD FAILED]\x1b[0m");
+        report.log(log);
+        None
     }
 }
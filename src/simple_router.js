@@ -1,5 +1,9 @@
 window.router = {
   is404: window.router?.is404 ?? false,
+  /** @type {String} Layout of the current page. Empty for the default template. */
+  layout: document.currentScript?.dataset.layout ?? "",
+  /** Internal: Do not use */
+  _script: document.currentScript,
 
   /** @type {Object} */
   pageCache: {},
@@ -62,25 +66,31 @@ window.router = {
       );
 
     const dataURL = router._dataURL(href, includesOrigin);
+    const fullHref = router.joinPath(includesOrigin ? "" : location.origin, href);
 
     return router
       ._load(dataURL)
-      .then(() => {
+      .then((loaded) => {
+        if (!loaded) {
+          location.href = fullHref;
+          return;
+        }
         history.pushState({ ...state, dataURL, path: router.path }, "", href);
         window.dispatchEvent(new CustomEvent("navigate"));
       })
       .catch(() => {
         if (config.notFound == "") {
-          location.href = router.joinPath(
-            includesOrigin ? "" : location.origin,
-            href,
-          );
+          location.href = fullHref;
         } else {
           router
             ._load(
               router.joinPath(location.origin, config.notFound + ".page.json"),
             )
-            .then(() => {
+            .then((loaded) => {
+              if (!loaded) {
+                location.href = fullHref;
+                return;
+              }
               history.pushState(
                 {
                   ...state,
@@ -96,17 +106,67 @@ window.router = {
       });
   },
 
-  /** Internal: Do not use */
+  /**
+   * Internal: Do not use
+   * Replaces the elements the previous page added to `<head>`, keeping the ones both pages share.
+   */
+  _updateHead(html) {
+    const template = document.createElement("template");
+    template.innerHTML = html;
+    const added = [...template.content.children];
+
+    document.head.querySelectorAll("[data-sr-head]").forEach((el) => {
+      const i = added.findIndex((other) => other.isEqualNode(el));
+      if (i === -1) el.remove();
+      else added.splice(i, 1);
+    });
+    added.forEach((el) => {
+      // Scripts only run when created with createElement.
+      if (el.tagName === "SCRIPT") {
+        const script = document.createElement("script");
+        [...el.attributes].forEach((a) => script.setAttribute(a.name, a.value));
+        script.textContent = el.textContent;
+        el = script;
+      }
+      if (router._script?.parentNode === document.head) router._script.before(el);
+      else document.head.append(el);
+    });
+  },
+
+  /**
+   * Internal: Do not use
+   * @returns {Promise<Boolean>} false if the page uses a different layout, and needs a full page load.
+   */
   async _load(dataURL) {
     const page =
       router.pageCache[dataURL] ?? (await (await fetch(dataURL)).json());
+    router.pageCache[dataURL] = page;
+
+    if ((page.__layout ?? "") !== router.layout) return false;
 
     Object.entries(page).forEach(([prop, value]) => {
+      if (["__layout", "__attrs", "__head", "__props"].includes(prop)) return;
       document.querySelectorAll(`[data-sr-prop="${prop}"]`).forEach((el) => {
         el.innerHTML = value;
       });
     });
-    router.pageCache[dataURL] = page;
+    router._updateHead(page.__head ?? "");
+    document.querySelectorAll("[data-sr-attrs]").forEach((el) => {
+      el.dataset.srAttrs.split(" ").forEach((binding) => {
+        const [attr, prop] = binding.split("=");
+        const value = page.__attrs?.[prop];
+        if (value == null) el.removeAttribute(attr);
+        else el.setAttribute(attr, value);
+      });
+    });
+    const props = page.__props ?? [];
+    document.querySelectorAll("[data-sr-if]").forEach((el) => {
+      el.hidden = !props.includes(el.dataset.srIf);
+    });
+    document.querySelectorAll("[data-sr-else]").forEach((el) => {
+      el.hidden = props.includes(el.dataset.srElse);
+    });
+    return true;
   },
 };
 
@@ -118,9 +178,10 @@ if (config.updateAnchors) {
 window.addEventListener("popstate", (e) => {
   if (e.state.dataURL != null) {
     router.path = e.state.path;
-    router
-      ._load(e.state.dataURL)
-      .then(() => window.dispatchEvent(new CustomEvent("navigate")));
+    router._load(e.state.dataURL).then((loaded) => {
+      if (loaded) window.dispatchEvent(new CustomEvent("navigate"));
+      else location.reload();
+    });
   }
 });
 
@@ -0,0 +1,60 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use xml::{
+    writer::{self, XmlEvent as WriteEvent},
+    EmitterConfig, EventWriter,
+};
+
+pub const SITEMAP_FILE: &str = "sitemap.xml";
+pub const ROBOTS_FILE: &str = "robots.txt";
+
+/// Writes `sitemap.xml` listing `pages` (each given as its URL relative to the site and its last
+/// modification date), and `robots.txt` if enabled.
+pub fn write_sitemap<'a>(
+    robots: bool,
+    base_url: &str,
+    pages: impl IntoIterator<Item = (String, &'a str)>,
+    out_path: &Path,
+) -> Result<(), writer::Error> {
+    let base_url = base_url.trim_end_matches('/');
+    let out = BufWriter::new(File::create(out_path.join(SITEMAP_FILE))?);
+    let mut writer = EventWriter::new_with_config(
+        out,
+        EmitterConfig {
+            perform_indent: true,
+            ..Default::default()
+        },
+    );
+
+    writer.write(
+        WriteEvent::start_element("urlset")
+            .default_ns("http://www.sitemaps.org/schemas/sitemap/0.9"),
+    )?;
+    for (url, lastmod) in pages {
+        writer.write(WriteEvent::start_element("url"))?;
+        for (name, text) in [
+            ("loc", &(base_url.to_owned() + &url)[..]),
+            ("lastmod", lastmod),
+        ] {
+            writer.write(WriteEvent::start_element(name))?;
+            writer.write(WriteEvent::characters(text))?;
+            writer.write(WriteEvent::end_element())?;
+        }
+        writer.write(WriteEvent::end_element())?;
+    }
+    writer.write(WriteEvent::end_element())?;
+    writer.into_inner().flush()?;
+
+    if robots {
+        fs::write(
+            out_path.join(ROBOTS_FILE),
+            format!("User-agent: *\nAllow: /\n\nSitemap: {base_url}/{SITEMAP_FILE}\n"),
+        )?;
+    }
+
+    Ok(())
+}
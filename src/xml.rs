@@ -1,12 +1,25 @@
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, HashMap, HashSet},
     fs::File,
-    io::{self, BufReader, BufWriter, Write},
+    io::{self, BufReader, Read, Write},
     path::Path,
+    sync::Arc,
 };
 
+use crate::{
+    assets::Fingerprints,
+    collection::Collections,
+    component::Components,
+    data::Data,
+    highlight::Highlighter,
+    minify::{self, minify_html},
+};
+
+use serde_json::{json, Map, Value};
+
 use xml::{
     attribute::{Attribute, OwnedAttribute},
+    escape::{escape_str_attribute, escape_str_pcdata},
     name::OwnedName,
     namespace::Namespace,
     reader::{self, XmlEvent},
@@ -27,12 +40,83 @@ impl Write for MutBuf<'_> {
     }
 }
 
+/// Escapes text to put in an event written by a [`Template`].
+pub fn escape_text(text: &str) -> String {
+    escape_str_pcdata(text).into_owned()
+}
+
+/// Escapes an attribute value to put in an event written by a [`Template`].
+pub fn escape_attribute(value: &str) -> String {
+    escape_str_attribute(value).into_owned()
+}
+
+/// Elements that have no end tag in HTML.
+const VOID_ELEMENTS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Whether `name` is an element with no end tag in HTML, like `br`.
+pub fn is_void(name: &str) -> bool {
+    VOID_ELEMENTS
+        .iter()
+        .any(|void| void.eq_ignore_ascii_case(name))
+}
+
+/// Writes events as HTML. Void elements are self-closed, since browsers read an end tag like
+/// `</br>` as another element.
+struct HtmlWriter<W: Write> {
+    writer: EventWriter<W>,
+    /// Whether each open element is void, so its end is skipped.
+    open: Vec<bool>,
+}
+
+impl<W: Write> HtmlWriter<W> {
+    fn new(sink: W, config: EmitterConfig) -> Self {
+        Self {
+            writer: EventWriter::new_with_config(sink, config),
+            open: Vec::new(),
+        }
+    }
+
+    fn write<'a, E: Into<WriteEvent<'a>>>(&mut self, event: E) -> writer::Result<()> {
+        match event.into() {
+            WriteEvent::StartElement {
+                name, attributes, ..
+            } if is_void(name.local_name) => {
+                // Written as text, which isn't escaped.
+                let mut tag = format!("<{name}");
+                for attribute in attributes.iter() {
+                    tag.push_str(&format!(" {}=\"{}\"", attribute.name, attribute.value));
+                }
+                tag.push_str(" />");
+                self.open.push(true);
+                self.writer.write(WriteEvent::characters(&tag))
+            }
+            event @ WriteEvent::StartElement { .. } => {
+                self.open.push(false);
+                self.writer.write(event)
+            }
+            WriteEvent::EndElement { .. } if self.open.pop() == Some(true) => Ok(()),
+            event => self.writer.write(event),
+        }
+    }
+}
+
+#[derive(Debug)]
 pub enum TemplateError {
     Io(io::Error),
     Parse(reader::Error),
     Write(writer::Error),
     MissingProp(String),
     MalformedProp(String),
+    MissingComponent(String),
+    ComponentCycle(Vec<String>),
+    FrontMatter(String),
+    MissingCollection(String),
+    MalformedAttribute(String),
+    MalformedData(String),
+    MissingData(String),
 }
 
 impl From<io::Error> for TemplateError {
@@ -53,44 +137,421 @@ impl From<writer::Error> for TemplateError {
     }
 }
 
+/// Shows an element only when the page has (or, for `sr-else`, doesn't have) a property.
+#[derive(Clone)]
+struct Condition {
+    prop: String,
+    negate: bool,
+}
+
+/// Sets the attribute `attr` to the text of a property.
+#[derive(Clone)]
+struct Binding {
+    attr: String,
+    prop: String,
+}
+
 #[derive(Clone)]
 enum TemplateEvent {
     Xml(XmlEvent),
     LibraryInsert,
+    /// An element with `sr-for`, repeated for each value in the array at `path` when the page is
+    /// written.
+    For {
+        var: String,
+        path: String,
+        element: Vec<XmlEvent>,
+    },
     StartPlaceholder {
         prop: String,
+        default: Option<Vec<XmlEvent>>,
+        condition: Option<Condition>,
+        bindings: Vec<Binding>,
+        name: OwnedName,
+        attributes: Vec<OwnedAttribute>,
+        namespace: Namespace,
+    },
+    StartDynamic {
+        condition: Option<Condition>,
+        bindings: Vec<Binding>,
         name: OwnedName,
         attributes: Vec<OwnedAttribute>,
         namespace: Namespace,
     },
 }
 
+impl TemplateEvent {
+    /// The attributes of an element's start.
+    fn attributes_mut(&mut self) -> Option<&mut Vec<OwnedAttribute>> {
+        match self {
+            Self::Xml(XmlEvent::StartElement { attributes, .. })
+            | Self::StartPlaceholder { attributes, .. }
+            | Self::StartDynamic { attributes, .. } => Some(attributes),
+            _ => None,
+        }
+    }
+}
+
+/// Everything shared by the template and its layouts.
+pub struct TemplateContext {
+    pub parser_config: ParserConfig,
+    pub library_path: String,
+    pub components: Components,
+    pub collections: Collections,
+    pub data: Data,
+    pub highlighter: Option<Highlighter>,
+    pub fingerprints: Fingerprints,
+    pub minify: bool,
+}
+
+impl TemplateContext {
+    /// Expands components, collections and data, rewrites references to fingerprinted files, and
+    /// highlights code blocks in `events`.
+    fn prepare(&self, events: Vec<XmlEvent>) -> Result<Vec<XmlEvent>, TemplateError> {
+        self.prepare_with(events, true, None)
+    }
+
+    /// Like [`TemplateContext::prepare`], but leaves `sr-for` loops to be compiled.
+    fn prepare_template(&self, events: Vec<XmlEvent>) -> Result<Vec<XmlEvent>, TemplateError> {
+        self.prepare_with(events, false, None)
+    }
+
+    /// Like [`TemplateContext::prepare`], for a page in the directory `dir`, which relative
+    /// references to fingerprinted files are resolved against.
+    fn prepare_page(
+        &self,
+        events: Vec<XmlEvent>,
+        dir: Option<&str>,
+    ) -> Result<Vec<XmlEvent>, TemplateError> {
+        self.prepare_with(events, true, dir)
+    }
+
+    fn prepare_with(
+        &self,
+        events: Vec<XmlEvent>,
+        loops: bool,
+        dir: Option<&str>,
+    ) -> Result<Vec<XmlEvent>, TemplateError> {
+        let events = self.collections.expand(self.components.expand(events)?)?;
+        let events = if loops {
+            self.data.expand(events)?
+        } else {
+            self.data.expand_outside_loops(events)?
+        };
+        let events = self.fingerprints.rewrite(events, dir);
+        Ok(match &self.highlighter {
+            Some(highlighter) => highlighter.highlight(events),
+            None => events,
+        })
+    }
+
+    /// Whether `events` list a collection, directly or through a component.
+    pub fn lists_collection(&self, events: &[XmlEvent]) -> Result<bool, TemplateError> {
+        Ok(Collections::lists(
+            &self.components.expand(events.to_vec())?,
+        ))
+    }
+}
+
 pub struct Template {
     events: Vec<TemplateEvent>,
-    parser_config: ParserConfig,
-    library_path: String,
+    /// Elements added to `<head>` by layouts.
+    head: Vec<XmlEvent>,
+    layout: Option<String>,
+    /// Whether the template lists a collection, so pages have to be rendered again when it
+    /// changes.
+    lists_collection: bool,
+    context: Arc<TemplateContext>,
 }
 
 impl Template {
-    const PROPS_SPECIAL: [&'static str; 2] = ["__path", "__filename"];
+    /// The property with elements to add to `<head>`, before the library.
+    const HEAD: &'static str = "head";
+    const BIND: &'static str = "sr-attr-";
+    /// Properties of paginated pages that are missing on the first or last page.
+    const PROPS_PAGE_LINKS: [&'static str; 2] = ["__prev", "__next"];
+    const PROPS_SPECIAL: [&'static str; 6] = [
+        "__path",
+        "__filename",
+        "__page_number",
+        "__page_count",
+        "__prev",
+        "__next",
+    ];
 
     pub fn parse_from_file(
         path: &Path,
-        parser_config: ParserConfig,
-        library_path: String,
+        context: Arc<TemplateContext>,
     ) -> Result<Self, TemplateError> {
         let file = File::open(path)?;
-        let file = BufReader::new(file);
+        Self::parse(BufReader::new(file), context)
+    }
 
-        let parser = EventReader::new_with_config(file, parser_config.clone());
+    /// Parses a template from `source`, like [`Template::parse_from_file`].
+    pub fn parse(source: impl Read, context: Arc<TemplateContext>) -> Result<Self, TemplateError> {
+        let parser = EventReader::new_with_config(source, context.parser_config.clone());
+        let source = parser.into_iter().collect::<Result<Vec<_>, _>>()?;
+        let lists_collection = context.lists_collection(&source)?;
+        let source = context.prepare_template(source)?;
         let mut events = Vec::new();
-        for event in parser {
-            match event? {
-                XmlEvent::StartElement {
+        for event in Self::compile(source)? {
+            if let TemplateEvent::Xml(XmlEvent::EndElement { name }) = &event {
+                if name.to_string() == "head" {
+                    events.push(TemplateEvent::LibraryInsert);
+                }
+            }
+            events.push(event);
+        }
+
+        Ok(Self {
+            events,
+            head: Vec::new(),
+            layout: None,
+            lists_collection,
+            context,
+        })
+    }
+
+    /// Creates a layout named `layout` by filling this template's placeholders with `props`.
+    /// Placeholders in `props` are kept, and placeholders in this template that `props` does not
+    /// fill are passed through to the page.
+    pub fn extend(
+        &self,
+        layout: String,
+        props: HashMap<String, Vec<XmlEvent>>,
+    ) -> Result<Self, TemplateError> {
+        let mut props = props;
+        let mut head = self.head.clone();
+        let mut lists_collection = self.lists_collection;
+        if let Some(events) = props.remove(Self::HEAD) {
+            lists_collection |= self.context.lists_collection(&events)?;
+            head.extend(self.context.prepare(events)?);
+        }
+
+        let mut slots = HashMap::new();
+        let mut texts = HashMap::new();
+        for (prop, events) in props {
+            lists_collection |= self.context.lists_collection(&events)?;
+            let events = self.context.prepare_template(events)?;
+            texts.insert(prop.clone(), text_content(&events));
+            slots.insert(prop, Self::compile(events)?);
+        }
+
+        let mut events = Vec::new();
+        for event in &self.events {
+            match event {
+                TemplateEvent::StartPlaceholder {
+                    prop,
+                    condition,
+                    bindings,
+                    name,
+                    attributes,
+                    namespace,
+                    ..
+                } if slots.contains_key(prop) => {
+                    let attributes = attributes
+                        .iter()
+                        .filter(|a| a.name.to_string() != "sr-prop")
+                        .cloned()
+                        .collect();
+                    let (condition, bindings, attributes) =
+                        Self::fill_dynamic(condition.clone(), bindings, &texts, attributes);
+                    events.push(Self::start_dynamic(
+                        condition,
+                        bindings,
+                        name.clone(),
+                        attributes,
+                        namespace.clone(),
+                    ));
+                    events.extend(slots[prop].iter().cloned());
+                }
+                TemplateEvent::StartPlaceholder {
+                    prop,
+                    default,
+                    condition,
+                    bindings,
                     name,
                     attributes,
                     namespace,
                 } => {
+                    let (condition, bindings, attributes) =
+                        Self::fill_dynamic(condition.clone(), bindings, &texts, attributes.clone());
+                    events.push(TemplateEvent::StartPlaceholder {
+                        prop: prop.clone(),
+                        default: default.clone(),
+                        condition,
+                        bindings,
+                        name: name.clone(),
+                        attributes,
+                        namespace: namespace.clone(),
+                    });
+                }
+                TemplateEvent::StartDynamic {
+                    condition,
+                    bindings,
+                    name,
+                    attributes,
+                    namespace,
+                } => {
+                    let (condition, bindings, attributes) =
+                        Self::fill_dynamic(condition.clone(), bindings, &texts, attributes.clone());
+                    events.push(Self::start_dynamic(
+                        condition,
+                        bindings,
+                        name.clone(),
+                        attributes,
+                        namespace.clone(),
+                    ));
+                }
+                event => events.push(event.clone()),
+            }
+        }
+
+        Ok(Self {
+            events,
+            head,
+            layout: Some(layout),
+            lists_collection,
+            context: self.context.clone(),
+        })
+    }
+
+    pub fn lists_collection(&self) -> bool {
+        self.lists_collection
+    }
+
+    /// Decides the parts of an element in a layout that depend on properties the layout fills
+    /// (given as their text): the element is then always shown (or always hidden), and bound
+    /// attributes are set.
+    fn fill_dynamic(
+        condition: Option<Condition>,
+        bindings: &[Binding],
+        texts: &HashMap<String, String>,
+        mut attributes: Vec<OwnedAttribute>,
+    ) -> (Option<Condition>, Vec<Binding>, Vec<OwnedAttribute>) {
+        let condition = match condition {
+            Some(condition) if texts.contains_key(&condition.prop) => {
+                if condition.negate {
+                    attributes.push(OwnedAttribute::new(OwnedName::local("hidden"), ""));
+                }
+                None
+            }
+            condition => condition,
+        };
+
+        let mut unfilled = Vec::new();
+        for binding in bindings {
+            match texts.get(&binding.prop) {
+                Some(text) => {
+                    attributes.retain(|a| a.name.local_name != binding.attr);
+                    attributes.push(OwnedAttribute::new(
+                        OwnedName::local(binding.attr.as_str()),
+                        escape_attribute(text),
+                    ));
+                }
+                None => unfilled.push(binding.clone()),
+            }
+        }
+
+        (condition, unfilled, attributes)
+    }
+
+    /// The start of an element, which depends on the page if it has a condition or bindings.
+    fn start_dynamic(
+        condition: Option<Condition>,
+        bindings: Vec<Binding>,
+        name: OwnedName,
+        attributes: Vec<OwnedAttribute>,
+        namespace: Namespace,
+    ) -> TemplateEvent {
+        if condition.is_none() && bindings.is_empty() {
+            TemplateEvent::Xml(XmlEvent::StartElement {
+                name,
+                attributes,
+                namespace,
+            })
+        } else {
+            TemplateEvent::StartDynamic {
+                condition,
+                bindings,
+                name,
+                attributes,
+                namespace,
+            }
+        }
+    }
+
+    fn compile(source: Vec<XmlEvent>) -> Result<Vec<TemplateEvent>, TemplateError> {
+        let mut events = Vec::new();
+        // The `sr-if` property of each open element, and of the last closed element at each depth,
+        // which an `sr-else` element refers to.
+        let mut open: Vec<Option<String>> = Vec::new();
+        let mut last_if: Vec<Option<String>> = vec![None];
+        let mut source = source.into_iter();
+        let mut pending = None;
+        while let Some(event) = pending.take().or_else(|| source.next()) {
+            match event {
+                XmlEvent::StartElement {
+                    name,
+                    mut attributes,
+                    namespace,
+                } if attributes.iter().any(|a| a.name.local_name == "sr-for") => {
+                    let index = attributes
+                        .iter()
+                        .position(|a| a.name.local_name == "sr-for")
+                        .unwrap();
+                    let (var, path) = Data::parse_loop(&attributes.remove(index).value)?;
+                    let mut element = vec![XmlEvent::StartElement {
+                        name: name.clone(),
+                        attributes,
+                        namespace,
+                    }];
+                    element.extend(take_children(&mut source));
+                    element.push(XmlEvent::EndElement { name });
+
+                    // An `sr-else` can't follow a loop.
+                    if let Some(last) = last_if.last_mut() {
+                        *last = None;
+                    }
+                    events.push(TemplateEvent::For { var, path, element });
+                }
+                XmlEvent::StartElement {
+                    name,
+                    mut attributes,
+                    namespace,
+                } => {
+                    let mut condition = None;
+                    if let Some(index) =
+                        attributes.iter().position(|a| a.name.local_name == "sr-if")
+                    {
+                        let prop = attributes.remove(index).value.trim().to_owned();
+                        if !Self::is_prop_name(&prop) {
+                            return Err(TemplateError::MalformedProp(prop));
+                        }
+                        condition = Some(Condition {
+                            prop,
+                            negate: false,
+                        });
+                    } else if let Some(index) = attributes
+                        .iter()
+                        .position(|a| a.name.local_name == "sr-else")
+                    {
+                        attributes.remove(index);
+                        let prop = last_if.last_mut().and_then(Option::take).ok_or_else(|| {
+                            TemplateError::MalformedAttribute(String::from("sr-else"))
+                        })?;
+                        condition = Some(Condition { prop, negate: true });
+                    }
+                    open.push(
+                        condition
+                            .as_ref()
+                            .filter(|c| !c.negate)
+                            .map(|c| c.prop.clone()),
+                    );
+                    last_if.push(None);
+
+                    let (bindings, mut attributes) = Self::bindings(attributes)?;
+
                     let mut placeholder = None;
                     for OwnedAttribute {
                         name: attr_name,
@@ -104,44 +565,184 @@ impl Template {
                     }
 
                     if let Some(placeholder) = placeholder {
-                        if !placeholder.chars().all(|c| c.is_alphanumeric() || c == '_')
-                            || (placeholder.starts_with("__")
-                                && !Self::PROPS_SPECIAL.contains(&placeholder.as_str()))
-                        {
+                        if !Self::is_prop_name(&placeholder) {
                             return Err(TemplateError::MalformedProp(placeholder));
-                        } else {
-                            events.push(TemplateEvent::StartPlaceholder {
-                                prop: placeholder,
-                                name,
-                                attributes,
-                                namespace,
-                            });
                         }
-                    } else {
-                        events.push(TemplateEvent::Xml(XmlEvent::StartElement {
+
+                        // The placeholder's content is its default, unless given by `sr-default`.
+                        let children = take_children(&mut source);
+                        let default = match attributes
+                            .iter()
+                            .position(|a| a.name.local_name == "sr-default")
+                        {
+                            Some(index) => Some(vec![XmlEvent::Characters(escape_text(
+                                &attributes.remove(index).value,
+                            ))]),
+                            None => Some(children).filter(|children| {
+                                children
+                                    .iter()
+                                    .any(|event| !matches!(event, XmlEvent::Whitespace(_)))
+                            }),
+                        };
+                        pending = Some(XmlEvent::EndElement { name: name.clone() });
+
+                        events.push(TemplateEvent::StartPlaceholder {
+                            prop: placeholder,
+                            default,
+                            condition,
+                            bindings,
                             name,
                             attributes,
                             namespace,
-                        }))
+                        });
+                    } else {
+                        events.push(Self::start_dynamic(
+                            condition, bindings, name, attributes, namespace,
+                        ));
                     }
                 }
                 XmlEvent::EndElement { name } => {
-                    if name.to_string() == "head" {
-                        events.push(TemplateEvent::LibraryInsert);
+                    last_if.pop();
+                    if let Some(last) = last_if.last_mut() {
+                        *last = open.pop().flatten();
                     }
-                    events.push(TemplateEvent::Xml(XmlEvent::EndElement { name }))
+                    events.push(TemplateEvent::Xml(XmlEvent::EndElement { name }));
                 }
                 e => events.push(TemplateEvent::Xml(e)),
             }
         }
 
-        Ok(Self {
-            events,
-            parser_config,
-            library_path,
-        })
+        Ok(events)
     }
 
+    /// The properties whose placeholder is, or is inside, an element whose content isn't minified.
+    fn raw_props(&self) -> HashSet<&str> {
+        let mut raw_props = HashSet::new();
+        let mut depth = 0;
+        for event in &self.events {
+            let name = match event {
+                TemplateEvent::Xml(XmlEvent::EndElement { name }) => {
+                    if minify::is_raw(&name.local_name) {
+                        depth -= 1;
+                    }
+                    continue;
+                }
+                TemplateEvent::Xml(XmlEvent::StartElement { name, .. })
+                | TemplateEvent::StartDynamic { name, .. } => name,
+                TemplateEvent::StartPlaceholder { prop, name, .. } => {
+                    if depth > 0 || minify::is_raw(&name.local_name) {
+                        raw_props.insert(prop.as_str());
+                    }
+                    name
+                }
+                _ => continue,
+            };
+            if minify::is_raw(&name.local_name) {
+                depth += 1;
+            }
+        }
+        raw_props
+    }
+
+    /// Takes the attributes bound to properties with `sr-attr-*` out of `attributes`. Attributes
+    /// bound to data were set when preparing the source.
+    fn bindings(
+        attributes: Vec<OwnedAttribute>,
+    ) -> Result<(Vec<Binding>, Vec<OwnedAttribute>), TemplateError> {
+        let mut bindings = Vec::new();
+        let mut kept = Vec::new();
+        for attribute in attributes {
+            match attribute.name.local_name.strip_prefix(Self::BIND) {
+                Some(attr) => {
+                    let prop = attribute.value.trim().to_owned();
+                    if !Self::is_prop_name(&prop) {
+                        return Err(TemplateError::MalformedProp(prop));
+                    }
+                    bindings.push(Binding {
+                        attr: attr.to_owned(),
+                        prop,
+                    });
+                }
+                None => kept.push(attribute),
+            }
+        }
+        Ok((bindings, kept))
+    }
+
+    fn is_prop_name(prop: &str) -> bool {
+        prop.chars().all(|c| c.is_alphanumeric() || c == '_')
+            && (!prop.starts_with("__") || Self::PROPS_SPECIAL.contains(&prop))
+    }
+
+    /// The attributes of an element that depend on the page. An element with a condition is
+    /// hidden if the condition doesn't hold, and the condition is kept in a `data-sr-if` or
+    /// `data-sr-else` attribute for the router. Bound attributes are set to the text of their
+    /// property (or keep their value in the template as a default), and listed in `data-sr-attrs`.
+    fn dynamic_attributes(
+        condition: Option<&Condition>,
+        bindings: &[Binding],
+        mut attributes: Vec<OwnedAttribute>,
+        bound_text: &dyn Fn(&Binding) -> Option<String>,
+        with_value: &HashSet<&str>,
+        optional: &HashSet<&str>,
+        json_attrs: &mut Map<String, Value>,
+    ) -> Result<Vec<OwnedAttribute>, TemplateError> {
+        if let Some(condition) = condition {
+            attributes.retain(|a| a.name.local_name != "hidden");
+            let name = if condition.negate {
+                "data-sr-else"
+            } else {
+                "data-sr-if"
+            };
+            attributes.push(OwnedAttribute::new(
+                OwnedName::local(name),
+                condition.prop.as_str(),
+            ));
+            if with_value.contains(condition.prop.as_str()) == condition.negate {
+                attributes.push(OwnedAttribute::new(OwnedName::local("hidden"), ""));
+            }
+        }
+
+        if bindings.is_empty() {
+            return Ok(attributes);
+        }
+        for binding in bindings {
+            let index = attributes
+                .iter()
+                .position(|a| a.name.local_name == binding.attr);
+            let value = match (bound_text(binding), index) {
+                (Some(text), _) => text,
+                (None, Some(index)) => attributes[index].value.clone(),
+                (None, None) if optional.contains(binding.prop.as_str()) => continue,
+                (None, None) => return Err(TemplateError::MissingProp(binding.prop.clone())),
+            };
+            if let Some(index) = index {
+                attributes.remove(index);
+            }
+
+            attributes.push(OwnedAttribute::new(
+                OwnedName::local(binding.attr.as_str()),
+                escape_attribute(&value),
+            ));
+            json_attrs
+                .entry(binding.prop.clone())
+                .or_insert(Value::String(value));
+        }
+        attributes.push(OwnedAttribute::new(
+            OwnedName::local("data-sr-attrs"),
+            bindings
+                .iter()
+                .map(|binding| format!("{}={}", binding.attr, binding.prop))
+                .collect::<Vec<_>>()
+                .join(" "),
+        ));
+
+        Ok(attributes)
+    }
+
+    /// Events are written without escaping, so that text which is already escaped, like a
+    /// Markdown page or highlighted code, is written as-is. Anything else put in an event has to be
+    /// escaped with [`escape_text`] or [`escape_attribute`].
     fn writer_config() -> EmitterConfig {
         EmitterConfig {
             normalize_empty_elements: false,
@@ -151,45 +752,94 @@ impl Template {
         }
     }
 
-    pub fn write_to_file(
-        &self,
-        source: BufReader<File>,
-        out: BufWriter<File>,
-        out_json: BufWriter<File>,
-        mut props_map: HashMap<String, Vec<XmlEvent>>,
-        is_404: bool,
-    ) -> Result<(), TemplateError> {
-        let parser = EventReader::new_with_config(source, self.parser_config.clone());
-        let mut current_prop = None;
-        let mut current_events = Vec::new();
-
-        for event in parser {
-            if current_prop.is_none() {
-                if let XmlEvent::StartElement { name, .. } = event? {
-                    current_prop = Some(name.to_string());
-                }
-            } else {
-                match event? {
-                    XmlEvent::EndElement { name } => {
-                        if current_prop
-                            .as_ref()
-                            .is_some_and(|p| name.to_string() == *p)
-                        {
-                            props_map.insert(current_prop.take().unwrap(), current_events);
-                            current_events = Vec::new();
-                        } else {
-                            current_events.push(XmlEvent::EndElement { name })
-                        }
-                    }
-                    event => current_events.push(event),
+    /// The events of the template with every `sr-for` loop repeated, and the repeated elements
+    /// compiled like the rest of the template, since they can have conditions and bindings.
+    fn expand_loops(&self) -> Result<Vec<TemplateEvent>, TemplateError> {
+        let mut events = Vec::with_capacity(self.events.len());
+        for event in &self.events {
+            match event {
+                TemplateEvent::For { var, path, element } => {
+                    let repeated = self.context.data.repeat(var, path, element)?;
+                    events.extend(Self::compile(repeated)?);
                 }
+                event => events.push(event.clone()),
             }
         }
+        Ok(events)
+    }
 
-        let mut writer = EventWriter::new_with_config(out, Self::writer_config());
+    /// Writes a page with `props_map` to `out`, and its properties to `out_json` for the router.
+    /// Returns the number of bytes saved by minifying, if enabled.
+    pub fn write_to_file(
+        &self,
+        mut out: impl Write,
+        out_json: impl Write,
+        props_map: HashMap<String, Vec<XmlEvent>>,
+        is_404: bool,
+    ) -> Result<usize, TemplateError> {
+        // The directory of the page, which relative URLs are resolved against. The 404 page is
+        // shown at any URL, so its relative URLs are left alone.
+        let dir = props_map.get("__path").filter(|_| !is_404).map(|path| {
+            let path = text_content(path);
+            match path.rfind('/') {
+                Some(end) => format!("/{}/", &path[..end]),
+                None => String::from("/"),
+            }
+        });
+        let dir = dir.as_deref();
+        let props_map = props_map
+            .into_iter()
+            .map(|(prop, events)| Ok((prop, self.context.prepare_page(events, dir)?)))
+            .collect::<Result<HashMap<_, _>, TemplateError>>()?;
+        // The text of a bound property, with a fingerprinted URL in `src` and `href`.
+        let bound_text = |binding: &Binding| {
+            let text = prop_text(&props_map, &binding.prop)?;
+            Some(match binding.attr.as_str() {
+                "src" | "href" => self.context.fingerprints.get(&text, dir).unwrap_or(text),
+                _ => text,
+            })
+        };
+        let mut html = Vec::new();
+        let mut writer = HtmlWriter::new(&mut html, Self::writer_config());
         let mut json_map = HashMap::new();
+        let mut json_attrs = Map::new();
+        let events = self.expand_loops()?;
+        let optional: HashSet<&str> = events
+            .iter()
+            .filter_map(|event| match event {
+                TemplateEvent::StartDynamic {
+                    condition: Some(condition),
+                    ..
+                }
+                | TemplateEvent::StartPlaceholder {
+                    condition: Some(condition),
+                    ..
+                } => Some(condition.prop.as_str()),
+                _ => None,
+            })
+            .chain(Self::PROPS_PAGE_LINKS)
+            .collect();
+        // Properties given by the page, or by a placeholder's default.
+        let with_value: HashSet<&str> = events
+            .iter()
+            .filter_map(|event| match event {
+                TemplateEvent::StartPlaceholder {
+                    prop,
+                    default: Some(_),
+                    ..
+                } => Some(prop.as_str()),
+                _ => None,
+            })
+            .chain(props_map.keys().map(String::as_str))
+            .collect();
 
-        for event in self.events.clone() {
+        for mut event in events.iter().cloned() {
+            // References in the template were rewritten when it was parsed, except relative ones.
+            if let Some(attributes) = event.attributes_mut() {
+                self.context
+                    .fingerprints
+                    .rewrite_attributes(attributes, dir);
+            }
             match event {
                 TemplateEvent::Xml(xml_event) => {
                     let writer_event = xml_event.as_writer_event();
@@ -200,6 +850,57 @@ impl Template {
                     }
                 }
                 TemplateEvent::LibraryInsert => {
+                    // The router replaces elements marked with `data-sr-head` when navigating.
+                    let mut json_buf = Vec::new();
+                    let mut json_writer = HtmlWriter::new(
+                        MutBuf(&mut json_buf),
+                        EmitterConfig {
+                            perform_indent: false,
+                            ..Self::writer_config()
+                        },
+                    );
+                    let mut depth = 0;
+                    let head = self.context.fingerprints.rewrite(self.head.clone(), dir);
+                    for event in head
+                        .iter()
+                        .chain(props_map.get(Self::HEAD).into_iter().flatten())
+                    {
+                        let writer_event = match event {
+                            XmlEvent::StartElement {
+                                name,
+                                attributes,
+                                namespace,
+                            } => {
+                                let mut attributes: Vec<_> =
+                                    attributes.iter().map(OwnedAttribute::borrow).collect();
+                                if depth == 0 {
+                                    attributes.push(Attribute::new("data-sr-head".into(), ""));
+                                }
+                                depth += 1;
+                                WriteEvent::StartElement {
+                                    name: name.borrow(),
+                                    attributes: attributes.into(),
+                                    namespace: namespace.borrow(),
+                                }
+                            }
+                            event => {
+                                if let XmlEvent::EndElement { .. } = event {
+                                    depth -= 1;
+                                }
+                                match event.as_writer_event() {
+                                    Some(writer_event) => writer_event,
+                                    None => continue,
+                                }
+                            }
+                        };
+                        writer.write(writer_event.clone())?;
+                        json_writer.write(writer_event)?;
+                    }
+                    json_map.insert(
+                        String::from("__head"),
+                        Value::String(String::from_utf8(json_buf).unwrap()),
+                    );
+
                     if is_404 {
                         writer
                             .write::<WriteEvent<'_>>(WriteEvent::start_element("script").into())?;
@@ -208,20 +909,56 @@ impl Template {
                             WriteEvent::end_element().name("script").into(),
                         )?;
                     }
-                    writer.write::<WriteEvent<'_>>(
-                        WriteEvent::start_element("script")
-                            .attr("src", &(String::from("/") + &self.library_path))
-                            .into(),
-                    )?;
+                    let src = String::from("/") + &self.context.library_path;
+                    let mut script = WriteEvent::start_element("script").attr("src", &src);
+                    if let Some(layout) = &self.layout {
+                        script = script.attr("data-layout", layout);
+                    }
+                    writer.write::<WriteEvent<'_>>(script.into())?;
                     writer
                         .write::<WriteEvent<'_>>(WriteEvent::end_element().name("script").into())?;
                 }
+                TemplateEvent::For { .. } => unreachable!("loops are expanded before writing"),
+                TemplateEvent::StartDynamic {
+                    condition,
+                    bindings,
+                    name,
+                    attributes,
+                    namespace,
+                } => {
+                    let attributes = Self::dynamic_attributes(
+                        condition.as_ref(),
+                        &bindings,
+                        attributes,
+                        &bound_text,
+                        &with_value,
+                        &optional,
+                        &mut json_attrs,
+                    )?;
+                    writer.write(WriteEvent::StartElement {
+                        name: name.borrow(),
+                        attributes: attributes.iter().map(OwnedAttribute::borrow).collect(),
+                        namespace: namespace.borrow(),
+                    })?;
+                }
                 TemplateEvent::StartPlaceholder {
                     prop,
+                    default,
+                    condition,
+                    bindings,
                     name,
                     attributes,
                     namespace,
                 } => {
+                    let attributes = Self::dynamic_attributes(
+                        condition.as_ref(),
+                        &bindings,
+                        attributes,
+                        &bound_text,
+                        &with_value,
+                        &optional,
+                        &mut json_attrs,
+                    )?;
                     let xml_event = WriteEvent::StartElement {
                         name: name.borrow(),
                         attributes: attributes
@@ -241,8 +978,16 @@ impl Template {
                     };
                     writer.write(xml_event)?;
 
+                    let Some(events) = props_map.get(&prop).or(default.as_ref()) else {
+                        // Properties used in a condition are optional.
+                        if optional.contains(prop.as_str()) {
+                            continue;
+                        }
+                        return Err(TemplateError::MissingProp(prop));
+                    };
+
                     let mut json_buf = Vec::new();
-                    let mut json_writer = EventWriter::new_with_config(
+                    let mut json_writer = HtmlWriter::new(
                         MutBuf(&mut json_buf),
                         EmitterConfig {
                             perform_indent: false,
@@ -250,25 +995,321 @@ impl Template {
                         },
                     );
 
-                    for event in props_map
-                        .get(&prop)
-                        .ok_or(TemplateError::MissingProp(prop.clone()))?
-                    {
-                        let writer_event = event.as_writer_event();
-                        if let Some(writer_event) = writer_event {
+                    for event in events {
+                        // The page's own elements can be bound to its properties too.
+                        let bound;
+                        let event = match event {
+                            XmlEvent::StartElement {
+                                name,
+                                attributes,
+                                namespace,
+                            } if attributes
+                                .iter()
+                                .any(|a| a.name.local_name.starts_with(Self::BIND)) =>
+                            {
+                                let (bindings, attributes) = Self::bindings(attributes.clone())?;
+                                bound = XmlEvent::StartElement {
+                                    name: name.clone(),
+                                    attributes: Self::dynamic_attributes(
+                                        None,
+                                        &bindings,
+                                        attributes,
+                                        &bound_text,
+                                        &with_value,
+                                        &optional,
+                                        &mut json_attrs,
+                                    )?,
+                                    namespace: namespace.clone(),
+                                };
+                                &bound
+                            }
+                            event => event,
+                        };
+                        if let Some(writer_event) = event.as_writer_event() {
                             writer.write(writer_event.clone())?;
                             json_writer.write(writer_event)?;
                         }
                     }
 
-                    json_map.insert(prop, String::from_utf8(json_buf).unwrap());
+                    json_map.insert(prop, Value::String(String::from_utf8(json_buf).unwrap()));
                 }
             }
         }
 
+        json_map.insert(
+            String::from("__layout"),
+            Value::String(self.layout.clone().unwrap_or_default()),
+        );
+        drop(writer);
+
+        let mut saved = 0;
+        if self.context.minify {
+            let minified = minify_html(&String::from_utf8_lossy(&html));
+            saved += html.len().saturating_sub(minified.len());
+            html = minified.into_bytes();
+
+            // Properties inside `<pre>` or `<textarea>` are kept as they are, like in the page.
+            let raw = self.raw_props();
+            for (prop, value) in &mut json_map {
+                if prop == "__layout" || raw.contains(prop.as_str()) {
+                    continue;
+                }
+                if let Value::String(text) = value {
+                    let minified = minify_html(text);
+                    saved += text.len().saturating_sub(minified.len());
+                    *text = minified;
+                }
+            }
+        }
+        out.write_all(&html)?;
+
+        if !json_attrs.is_empty() {
+            json_map.insert(String::from("__attrs"), Value::Object(json_attrs));
+        }
+        // The router shows and hides conditional elements by the properties the page has.
+        let mut defined: Vec<&str> = optional.intersection(&with_value).copied().collect();
+        if !defined.is_empty() {
+            defined.sort_unstable();
+            json_map.insert(String::from("__props"), json!(defined));
+        }
         let ordered: BTreeMap<_, _> = json_map.iter().collect();
         serde_json::to_writer(out_json, &ordered).unwrap();
 
-        Ok(())
+        Ok(saved)
+    }
+}
+
+/// Marks the properties of a page parsed from Markdown, whose text is kept escaped.
+pub const MARKDOWN_PROP: &str = "__markdown";
+
+/// Parses a page (or child layout) into its properties, which are the page's root elements.
+pub fn parse_page(
+    source: impl Read,
+    parser_config: ParserConfig,
+) -> Result<HashMap<String, Vec<XmlEvent>>, TemplateError> {
+    let parser = EventReader::new_with_config(source, parser_config);
+    let mut props_map = HashMap::new();
+    let mut current_prop = None;
+    let mut current_events = Vec::new();
+
+    for event in parser {
+        if current_prop.is_none() {
+            if let XmlEvent::StartElement { name, .. } = event? {
+                current_prop = Some(name.to_string());
+            }
+        } else {
+            match event? {
+                XmlEvent::EndElement { name } => {
+                    if current_prop
+                        .as_ref()
+                        .is_some_and(|p| name.to_string() == *p)
+                    {
+                        props_map.insert(current_prop.take().unwrap(), current_events);
+                        current_events = Vec::new();
+                    } else {
+                        current_events.push(XmlEvent::EndElement { name })
+                    }
+                }
+                event => current_events.push(event),
+            }
+        }
+    }
+
+    Ok(props_map)
+}
+
+/// Writes `events` as XML, like a page would contain them.
+pub fn write_events(events: &[XmlEvent]) -> Result<String, TemplateError> {
+    let mut xml = Vec::new();
+    let mut writer = EventWriter::new_with_config(
+        &mut xml,
+        EmitterConfig {
+            normalize_empty_elements: false,
+            write_document_declaration: false,
+            perform_indent: false,
+            ..Default::default()
+        },
+    );
+    for event in events.iter().filter_map(XmlEvent::as_writer_event) {
+        writer.write(event)?;
+    }
+    Ok(String::from_utf8_lossy(&xml).into_owned())
+}
+
+/// The text inside a property, ignoring any markup.
+pub fn text_content(events: &[XmlEvent]) -> String {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            XmlEvent::Characters(text) | XmlEvent::CData(text) | XmlEvent::Whitespace(text) => {
+                Some(text.as_str())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The text of `prop` in the properties of a page. Properties parsed from Markdown are kept
+/// escaped, so their escaped characters are unescaped.
+pub fn prop_text(props: &HashMap<String, Vec<XmlEvent>>, prop: &str) -> Option<String> {
+    let text = text_content(props.get(prop)?);
+    if !props.contains_key(MARKDOWN_PROP) {
+        return Some(text);
+    }
+
+    let text = [
+        ("&lt;", "<"),
+        ("&gt;", ">"),
+        ("&quot;", "\""),
+        ("&apos;", "'"),
+        ("&#39;", "'"),
+        ("&amp;", "&"),
+    ]
+    .into_iter()
+    .fold(text, |text, (escaped, c)| text.replace(escaped, c));
+    Some(text)
+}
+
+/// Takes the events up to the end of the current element, consuming the end element.
+pub fn take_children(events: &mut impl Iterator<Item = XmlEvent>) -> Vec<XmlEvent> {
+    let mut children = Vec::new();
+    let mut depth = 0;
+
+    for event in events {
+        match event {
+            XmlEvent::StartElement { .. } => depth += 1,
+            XmlEvent::EndElement { .. } if depth == 0 => break,
+            XmlEvent::EndElement { .. } => depth -= 1,
+            _ => (),
+        }
+        children.push(event);
+    }
+
+    children
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(data: Data, minify: bool) -> Arc<TemplateContext> {
+        Arc::new(TemplateContext {
+            parser_config: ParserConfig::default(),
+            library_path: String::from("simple-router.js"),
+            components: Components::default(),
+            collections: Collections::default(),
+            data,
+            highlighter: None,
+            fingerprints: Fingerprints::default(),
+            minify,
+        })
+    }
+
+    /// Renders a page with `props`, returning its HTML and JSON.
+    fn render(
+        context: Arc<TemplateContext>,
+        template: &str,
+        props: HashMap<String, Vec<XmlEvent>>,
+    ) -> (String, Value) {
+        let template = Template::parse(template.as_bytes(), context).unwrap();
+        let mut html = Vec::new();
+        let mut json = Vec::new();
+        template
+            .write_to_file(&mut html, &mut json, props, false)
+            .unwrap();
+        (
+            String::from_utf8(html).unwrap(),
+            serde_json::from_slice(&json).unwrap(),
+        )
+    }
+
+    fn minified_json(template: &str, page: &str) -> Value {
+        let props = parse_page(page.as_bytes(), ParserConfig::default()).unwrap();
+        render(context(Data::default(), true), template, props).1
+    }
+
+    #[test]
+    fn keeps_raw_props_in_json() {
+        let json = minified_json(
+            "<html><head></head><body><div sr-prop=\"intro\" /><pre sr-prop=\"code\" />\
+             <form><textarea sr-prop=\"text\" /></form></body></html>",
+            "<intro>\n  <p>  Hi  </p>\n</intro><code>fn main() {\n    run();\n}</code>\
+             <text>  a\n  b</text>",
+        );
+        assert_eq!(json["intro"], "<p>Hi</p>");
+        assert_eq!(json["code"], "fn main() {\n    run();\n}");
+        assert_eq!(json["text"], "  a\n  b");
+    }
+
+    #[test]
+    fn keeps_props_inside_pre_in_json() {
+        let json = minified_json(
+            "<html><head></head><body><pre><code sr-prop=\"code\" /></pre></body></html>",
+            "<code>a\n    b</code>",
+        );
+        assert_eq!(json["code"], "a\n    b");
+    }
+
+    #[test]
+    fn self_closes_markdown_line_breaks() {
+        let props =
+            crate::markdown::parse_page("One  \ntwo<br>three", ParserConfig::default(), None)
+                .unwrap();
+        let (html, json) = render(
+            context(Data::default(), false),
+            "<html><head></head><body><main sr-prop=\"content\" /></body></html>",
+            props,
+        );
+        assert!(html.contains("<p>One<br />\ntwo<br />three</p>"));
+        assert!(!html.contains("</br>"));
+        assert_eq!(json["content"], "<p>One<br />\ntwo<br />three</p>\n");
+    }
+
+    #[test]
+    fn compiles_conditions_and_bindings_in_loops() {
+        let mut data = Data::default();
+        data.insert("nav", json!([{ "title": "Home" }, { "title": "About" }]));
+        let props = parse_page("<title>Hi</title>".as_bytes(), ParserConfig::default()).unwrap();
+        let (html, _) = render(
+            context(data, false),
+            "<html><head></head><body>\
+             <a sr-for=\"link in data.nav\" sr-attr-title=\"title\" sr-text=\"link.title\" />\
+             <b sr-for=\"link in data.nav\" sr-if=\"sidebar\" sr-text=\"link.title\" />\
+             </body></html>",
+            props,
+        );
+        assert!(html.contains(
+            "<a title=\"Hi\" data-sr-attrs=\"title=title\">Home</a>\
+             <a title=\"Hi\" data-sr-attrs=\"title=title\">About</a>"
+        ));
+        assert!(html.contains(
+            "<b data-sr-if=\"sidebar\" hidden=\"\">Home</b><b data-sr-if=\"sidebar\" hidden=\"\">About</b>"
+        ));
+    }
+
+    #[test]
+    fn binds_page_links_on_first_and_last_pages() {
+        let template = "<html><head></head><body><main sr-prop=\"content\" />\
+                        <a sr-if=\"__next\" sr-attr-href=\"__next\">Next</a></body></html>";
+        let page = "<content><a sr-attr-href=\"__prev\">Prev</a></content>";
+
+        let first = parse_page(page.as_bytes(), ParserConfig::default()).unwrap();
+        let (html, _) = render(context(Data::default(), false), template, first);
+        assert!(html.contains("<a data-sr-attrs=\"href=__prev\">Prev</a>"));
+        assert!(
+            html.contains("<a data-sr-if=\"__next\" hidden=\"\" data-sr-attrs=\"href=__next\">")
+        );
+
+        let mut last = parse_page(page.as_bytes(), ParserConfig::default()).unwrap();
+        last.insert(
+            String::from("__prev"),
+            vec![XmlEvent::Characters(String::from("/blog.html"))],
+        );
+        let (html, json) = render(context(Data::default(), false), template, last);
+        assert!(html.contains("<a href=\"/blog.html\" data-sr-attrs=\"href=__prev\">Prev</a>"));
+        assert!(
+            html.contains("<a data-sr-if=\"__next\" hidden=\"\" data-sr-attrs=\"href=__next\">")
+        );
+        assert_eq!(json["__attrs"]["__prev"], "/blog.html");
     }
 }